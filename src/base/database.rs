@@ -2,9 +2,49 @@
 
 use std::{borrow::Cow, ops::Range};
 use crate::errors::Error;
-use self::allocator::Allocator;
-use super::layer::Layer;
+use self::{allocator::Allocator, cache::Cache, compaction::{CompactionMode, CompactionPolicy}, resolved::ResolvedMap};
+use super::layer::{mapper::LazyData, Layer, LayerStream, LayerWriter, SectionData, BUFFER_SIZE};
 pub mod allocator;
+pub mod cache;
+pub mod compaction;
+pub mod resolved;
+pub mod retention;
+pub mod stats;
+pub mod transaction;
+
+/// A savepoint within the heap layer of a `StackDB` to roll back to; the index of the savepoint & the heap layer it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint(usize, u64);
+
+/// A range of the database that wasn't read from raw bytes; either a fill (with its byte) or a hole (`None`)
+type Unread = (Range<u64>, Option<u8>);
+
+/// A range of the database and the layer it belongs to
+type Extent = (Range<u64>, usize);
+
+/// Finds the combined bounds of the layers
+#[inline]
+fn hull<S: LayerStream>(layers: &[Layer<'_, S>]) -> Option<Range<u64>> {
+    layers.iter()
+        .filter_map(|x| x.bounds.as_ref())
+        .fold(None, |x: Option<Range<u64>>, y| Some(match x {
+            Some(x) => std::cmp::min(x.start, y.start)..std::cmp::max(x.end, y.end),
+            None => y.clone(),
+        }))
+}
+
+/// Merges the ranges into ordered, non-overlapping ones
+#[inline]
+fn merge(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    ranges.sort_unstable_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = std::cmp::max(last.end, r.end),
+            _ => merged.push(r),
+        }
+    } merged
+}
 
 #[derive(Debug)]
 pub struct StackDB<'l, A: Allocator<'l>> {
@@ -12,8 +52,22 @@ pub struct StackDB<'l, A: Allocator<'l>> {
     alloc: A,
     /// If there is a heap layer or not
     heap_layer: bool,
+    /// The amount of heap layers created so far (so savepoints of committed heap layers can be told apart)
+    heap_generation: u64,
     /// The actual layers in the database
     layers: Vec<Layer<'l, A::LayerStream>>,
+    /// The amount of times the layers have been rewritten (rebased)
+    epoch: u64,
+    /// The policy for automatically compacting the database
+    policy: Option<CompactionPolicy>,
+    /// The amount of commits since the last compaction
+    commits: usize,
+    /// The error of the last failed inline compaction (that its commit still went through despite)
+    compaction_error: Option<Error>,
+    /// The cache of the data read from committed layers
+    cache: Cache,
+    /// The maintained map of which committed layer each extent belongs to (built on first use)
+    resolved: Option<ResolvedMap>,
 }
 
 impl<'l, A: Allocator<'l>> StackDB<'l, A> {
@@ -22,11 +76,30 @@ impl<'l, A: Allocator<'l>> StackDB<'l, A> {
     pub fn new(alloc: A) -> Result<Self, Error> {
         Ok(Self {
             heap_layer: false,
+            heap_generation: 0,
             layers: alloc.load_layers()?,
             alloc,
+            epoch: 0,
+            policy: None,
+            commits: 0,
+            compaction_error: None,
+            cache: Cache::default(),
+            resolved: None,
         })
     }
 
+    /// Grabs the layer allocator of the database
+    #[inline]
+    pub fn alloc(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Grabs the layer allocator of the database mutably; it must not add or remove any layers behind the database's back
+    #[inline]
+    pub fn alloc_mut(&mut self) -> &mut A {
+        &mut self.alloc
+    }
+
     /// Either grabs the heap layer or creates a new one
     #[inline]
     fn get_heap_layer(&mut self) -> Result<&mut Layer<'l, A::LayerStream>, Error> {
@@ -36,6 +109,7 @@ impl<'l, A: Allocator<'l>> StackDB<'l, A> {
 
         self.layers.push(self.alloc.add_layer()?);
         self.heap_layer = true;
+        self.heap_generation += 1;
         self.get_heap_layer()
     }
 
@@ -43,18 +117,134 @@ impl<'l, A: Allocator<'l>> StackDB<'l, A> {
     #[inline]
     pub fn read(&mut self, addr: Range<u64>) -> Result<Box<[u8]>, Error> {
         let mut data = vec![0u8; (addr.end-addr.start) as usize].into_boxed_slice();
-        let mut missing: Vec<Range<u64>> = vec![addr.clone()]; // data that hasn't been read yet
+        self.read_into(addr, &mut data)?;
+        Ok(data)
+    }
+
+    /// Reads data from either the heap or disk layers into the buffer (which must be the same length as the range)
+    #[inline]
+    pub fn read_into(&mut self, addr: Range<u64>, buffer: &mut [u8]) -> Result<(), Error> {
+        if buffer.len() as u64 != addr.end.saturating_sub(addr.start) { return Err(Error::OutOfBounds) };
+        let unread = self.read_below(self.layers.len(), addr, buffer)?;
+
+        if unread.iter().any(|(_, fill)| fill.is_none()) { return Err(Error::OutOfBounds) } // note: otherwise it will just return 0s for the areas not covered by layers
 
-        #[inline]
-        fn write_into(data: &[u8], out: &mut [u8]) {
-            data.iter()
-                .enumerate()
-                .for_each(|(i, b)| out[i] = *b);
+        Ok(())
+    }
+
+    /// Reads data from either the heap or disk layers; borrowed straight from the heap layer if it's covered by a single uncommitted write
+    #[inline]
+    pub fn read_cow(&mut self, addr: Range<u64>) -> Result<Cow<'_, [u8]>, Error> {
+        let single = self.heap_layer && matches!(self.layers.last_mut().unwrap().read_section_unchecked(&addr), Ok((_, SectionData::Bytes(_))));
+        if single {
+            return match self.layers.last_mut().unwrap().read_section_unchecked(&addr)? {
+                (r, SectionData::Bytes(Cow::Borrowed(x))) => Ok(Cow::Borrowed(&x[r])),
+                (r, SectionData::Bytes(Cow::Owned(x))) => Ok(Cow::Owned(x[r].to_vec())),
+                _ => Err(Error::OutOfBounds),
+            };
+        }
+
+        self.read(addr).map(|x| Cow::Owned(x.into_vec()))
+    }
+
+    /// Reads many (possibly scattered) ranges at once and returns their data in request order
+    ///
+    /// **note:** all the ranges are resolved together in a single descent through the layers, then read in address order (overlapping ranges only get read once)
+    pub fn read_many(&mut self, ranges: &[Range<u64>]) -> Result<Vec<Box<[u8]>>, Error> {
+        let merged = merge(ranges.iter().filter(|r| !r.is_empty()).cloned().collect());
+        let (extents, missing) = self.resolve_many(0..self.layers.len(), merged.clone())?;
+        if !missing.is_empty() { return Err(Error::OutOfBounds) };
+
+        // read the extents into the merged ranges they belong to
+        let mut data: Vec<Box<[u8]>> = merged.iter().map(|r| vec![0u8; (r.end-r.start) as usize].into_boxed_slice()).collect();
+        let mut unread = Vec::new();
+        for (range, i) in extents {
+            let j = merged.partition_point(|r| r.end <= range.start);
+            let out = &mut data[j][(range.start-merged[j].start) as usize..(range.end-merged[j].start) as usize];
+            self.read_extent(i, range, out, &mut unread)?;
         }
+        if unread.iter().any(|(_, fill)| fill.is_none()) { return Err(Error::OutOfBounds) };
+
+        Ok(ranges.iter().map(|r| {
+            if r.is_empty() { return Box::default() };
+            let j = merged.partition_point(|x| x.end <= r.start);
+            data[j][(r.start-merged[j].start) as usize..(r.end-merged[j].start) as usize].into()
+        }).collect())
+    }
+
+    /// Reads data from the layers below the `top` layer (resolving references) and returns the ranges that weren't read from raw bytes;
+    /// either fills (with their byte, also written to the data) or holes that no layer covers
+    fn read_below(&mut self, top: usize, addr: Range<u64>, data: &mut [u8]) -> Result<Vec<Unread>, Error> {
+        let (extents, missing) = self.resolve(0..top, addr.clone())?;
+        let mut unread = Vec::new(); // data that has been filled, deleted (or that references point to but hasn't been written)
+
+        for (range, i) in extents {
+            let out = &mut data[(range.start-addr.start) as usize..(range.end-addr.start) as usize];
+            self.read_extent(i, range, out, &mut unread)?;
+        }
+
+        unread.extend(missing.into_iter().map(|r| (r, None)));
+        Ok(unread)
+    }
+
+    /// Reads the extent of the layer into the buffer (resolving references) and adds the ranges that weren't read from raw bytes to `unread`
+    fn read_extent(&mut self, layer: usize, range: Range<u64>, out: &mut [u8], unread: &mut Vec<Unread>) -> Result<(), Error> {
+        let src = match self.layers[layer].locate_section_unchecked(&range)? {
+            (r, LazyData::OnDisk(pos)) => return self.read_cached(layer, pos + r.start as u64, out),
+            (r, LazyData::Loaded(SectionData::Ref(src))) => src + r.start as u64,
+            (r, LazyData::Loaded(SectionData::Bytes(x))) => { out.copy_from_slice(&x[r]); return Ok(()) },
+            (_, LazyData::Loaded(SectionData::Tombstone)) => { unread.push((range, None)); return Ok(()) },
+            (_, LazyData::Loaded(SectionData::Fill(byte))) => {
+                out.fill(byte);
+                unread.push((range, Some(byte)));
+                return Ok(());
+            },
+        };
+
+        // resolve the reference through the layers below
+        unread.extend(self.read_below(layer, src..src + (range.end-range.start), out)?
+            .into_iter()
+            .map(|(r, fill)| (r.start - src + range.start..r.end - src + range.start, fill)));
+        Ok(())
+    }
+
+    /// Finds which layer (of the run of layers, top-most first) each part of the range belongs to;
+    /// returns the ordered extents and the parts that none of the layers cover
+    ///
+    /// **note:** the committed layers are looked up through the resolved map when the run includes all of them
+    #[inline]
+    fn resolve(&mut self, layers: Range<usize>, addr: Range<u64>) -> Result<(Vec<Extent>, Vec<Range<u64>>), Error> {
+        self.resolve_many(layers, vec![addr])
+    }
+
+    /// Finds which layer (of the run of layers, top-most first) each part of the (non-overlapping) ranges belongs to, all in one go;
+    /// returns the ordered extents and the parts that none of the layers cover
+    fn resolve_many(&mut self, layers: Range<usize>, addrs: Vec<Range<u64>>) -> Result<(Vec<Extent>, Vec<Range<u64>>), Error> {
+        let committed = self.layers.len() - self.heap_layer as usize;
+        let (mut extents, missing) = if layers.start == 0 && layers.end >= committed && committed > 0 {
+            // the heap layer (if there is one), then the resolved view of the committed layers
+            let (mut extents, missing) = self.walk(committed..layers.end, addrs)?;
+            let resolved = self.resolved()?;
+            let mut gaps = Vec::new();
+            for miss in missing {
+                let (found, missing) = resolved.get(&miss);
+                extents.extend(found);
+                gaps.extend(missing);
+            } (extents, gaps)
+        } else { self.walk(layers, addrs)? };
+
+        extents.sort_unstable_by_key(|(r, _)| r.start);
+        Ok((extents, missing))
+    }
+
+    /// Walks through the run of layers (top-most first) to find which layer each part of the ranges belongs to;
+    /// returns the (unordered) extents and the parts that none of the layers cover
+    fn walk(&mut self, layers: Range<usize>, mut missing: Vec<Range<u64>>) -> Result<(Vec<Extent>, Vec<Range<u64>>), Error> {
+        let mut extents = Vec::new();
 
-        for layer in self.layers.iter_mut().rev() {
+        for i in layers.rev() {
             if missing.is_empty() { break };
-            let mut collisions = Vec::new();
+            let layer = &mut self.layers[i];
             let mut non_collisions = Vec::new();
 
             // find the parts of the range that belong to the layer's sections
@@ -63,55 +253,264 @@ impl<'l, A: Allocator<'l>> StackDB<'l, A> {
                 miss_collisions.sort_unstable_by_key(|r| r.start); // for later: fix the collisions function so that it's automatically sorted.
 
                 non_collisions.append(&mut layer.check_non_collisions(miss, &miss_collisions).into_vec());
-                collisions.append(&mut miss_collisions.into_vec());
+                extents.extend(miss_collisions.into_vec().into_iter().map(|r| (r, i)));
             } missing = non_collisions;
+        }
+
+        Ok((extents, missing))
+    }
+
+    /// Grabs the resolved map of the committed layers; building it (by walking through all of them) if it isn't maintained yet
+    fn resolved(&mut self) -> Result<&ResolvedMap, Error> {
+        if self.resolved.is_none() {
+            let committed = self.layers.len() - self.heap_layer as usize;
+            let mut extents = match hull(&self.layers[..committed]) {
+                Some(bounds) => self.walk(0..committed, vec![bounds])?.0,
+                None => Vec::new(),
+            };
+            extents.sort_unstable_by_key(|(r, _)| r.start);
+            self.resolved = Some(ResolvedMap::new(extents));
+        } Ok(self.resolved.as_ref().unwrap())
+    }
+
+    /// Drops everything derived from the committed layers (after they got rewritten)
+    #[inline]
+    fn invalidate(&mut self) {
+        self.cache.clear();
+        self.resolved = None;
+    }
+
+    /// Merges a run of adjacent committed layers into a single layer at the same position in the stack, leaving the other layers untouched
+    #[inline]
+    pub fn compact(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        if layers.start > layers.end || layers.end > self.layers.len() - self.heap_layer as usize { return Err(Error::OutOfBounds) };
+        if layers.len() < 2 { return Ok(()) }; // nothing to merge
+        self.merge_layers(layers, BUFFER_SIZE)
+    }
 
-            // actually read the values
-            for range in collisions.iter() {
-                let read = layer.read_unchecked(range)?;
-                write_into(&read.1[read.0], &mut data[(range.start-addr.start) as usize..(range.end-addr.start) as usize]);
+    /// Merges the run of committed layers into a single layer (buffering up to `buffer_size` bytes of it at a time) that atomically replaces them
+    fn merge_layers(&mut self, layers: Range<usize>, buffer_size: usize) -> Result<(), Error> {
+        // write the merged sections straight to the new layer (as of the latest commit)
+        let timestamp = self.layers[layers.clone()].iter().map(|x| x.timestamp).max().unwrap_or(0);
+        let mut writer = LayerWriter::with_capacity(self.alloc.add_replacement_layer()?, timestamp, buffer_size)?;
+        if let Some(bounds) = hull(&self.layers[layers.clone()]) {
+            for (range, i) in self.resolve(layers.clone(), bounds)?.0 {
+                self.merge_extent(layers.start, layers.start == 0, i, range.clone(), range.start, &mut writer)?;
             }
         }
 
-        if !missing.is_empty() { return Err(Error::OutOfBounds) } // note: otherwise it will just return 0s for the areas not covered by layers
+        // swap out the old layers
+        let layer = writer.finish()?;
+        self.alloc.replace_layers(layers.clone())?;
+        self.layers.splice(layers, [layer]);
+        self.invalidate();
+        self.epoch += 1;
+        self.commits = 0;
 
-        Ok(data)
+        Ok(())
+    }
+
+    /// Writes the sections of a layer's extent (merged down to the `floor` layer) to a layer writer at the destination address;
+    /// holes are only left out if the written layer ends up at the `bottom` of the database
+    fn merge_extent(&mut self, floor: usize, bottom: bool, layer: usize, range: Range<u64>, dst: u64, writer: &mut LayerWriter<A::LayerStream>) -> Result<(), Error> {
+        let src = match self.layers[layer].locate_section_unchecked(&range)? {
+            (r, LazyData::Loaded(SectionData::Ref(src))) => Some(src + r.start as u64),
+            (_, LazyData::Loaded(SectionData::Tombstone)) if bottom => return Ok(()), // nothing below to delete
+            _ => None,
+        };
+        let src = if let Some(x) = src { x } else { return writer.copy_section(&mut self.layers[layer], &range, dst) };
+
+        // resolve the reference through the merged layers; only referencing what's below them
+        let (extents, missing) = self.resolve(floor..layer, src..src + (range.end-range.start))?;
+        let mut parts = extents.into_iter()
+            .map(|(r, i)| (r, Some(i)))
+            .chain(missing.into_iter().map(|r| (r, None)))
+            .collect::<Vec<_>>();
+        parts.sort_unstable_by_key(|(r, _)| r.start);
+
+        for (r, i) in parts {
+            let to = r.start - src + dst;
+            match i {
+                Some(i) => self.merge_extent(floor, bottom, i, r, to, writer)?,
+                None if floor > 0 => writer.write_section(to..to + (r.end-r.start), &SectionData::Ref(r.start))?,
+                None if !bottom => writer.write_section(to..to + (r.end-r.start), &SectionData::Tombstone)?, // references to nothing
+                None => (),
+            }
+        } Ok(())
     }
 
     /// Rebases and drops overwritten layers (the database history)
-    /// by compressing all the layers into one to save space
+    /// by streaming the merged data of all the layers (buffering up to `buffer_size` bytes at a time) into a single new base layer to save space
     ///
-    /// **Warning:** will temporarity double database size
+    /// **Warning:** will temporarily take up to double the database size (see `rebase_streaming` to bound it)
     #[inline]
     pub fn rebase(&mut self, buffer_size: u64) -> Result<(), Error> {
-        if self.layers.is_empty() || self.layers.last().unwrap().bounds.is_none() { return Ok(()) }; // do nothing if database is empty
-        self.commit()?;
-        let old_layers = self.layers.len();
-
-        let db_bounds = self.layers.iter()
-            .filter_map(|x| x.bounds.as_ref())
-            .fold((u64::MAX, u64::MIN), |x, y| (std::cmp::min(x.0, y.start), std::cmp::max(x.1, y.end)));
-        let db_bounds = db_bounds.0..db_bounds.1;
-        
-        // Write all the changes into the top layer
-        let mut idx = db_bounds.start;
-        while idx < db_bounds.end {
-            let end = std::cmp::min(db_bounds.end, idx+buffer_size);
-            let buffer = self.read(idx..end)?;
-            self.write(idx, &buffer)?;
-            self.commit()?; // as to not bomb your memory
-            idx = end;
-        }
-
-        // Drop all the other layers
-        self.alloc.rebase(old_layers)?;
-        let mut layers = Vec::with_capacity(self.layers.len()-old_layers);
-        layers.extend(self.layers.drain(old_layers..));
-        self.layers = layers;
+        self.commit_heap()?;
+        self.drop_empty_heap()?;
+        if self.layers.is_empty() { return Ok(()) }; // do nothing if database is empty
+        self.merge_layers(0..self.layers.len(), buffer_size as usize)
+    }
+
+    /// Rebases like `rebase`, but releases old layers early to bound the extra space the rebase takes up
+    ///
+    /// The new base layer goes on top of the old layers; once it takes up more than `max_extra_space` bytes more than the layers released so far,
+    /// the part of it streamed so far is made valid and the old layers that nothing after the streamed data depends on are released
+    ///
+    /// **note:** an interrupted rebase leaves the stored database intact (reload it after an error), with the partial base layer on top of the old layers that weren't released yet;
+    /// allocators that can't release layers in place (see `Allocator::releases_in_place`) get rejected
+    pub fn rebase_streaming(&mut self, max_extra_space: u64) -> Result<(), Error> {
+        if !self.alloc.releases_in_place() { return Err(Error::Custom("the allocator can't release layers in place for a streaming rebase".into())) };
+        self.commit_heap()?;
+        self.drop_empty_heap()?;
+        let bounds = if let Some(x) = hull(&self.layers) { x } else { return Ok(()) }; // do nothing if database is empty
+
+        // find the data of the database & up to where each layer is depended on
+        let extents = self.resolve(0..self.layers.len(), bounds)?.0;
+        let mut release_points = vec![0; self.layers.len()];
+        for (range, i) in extents.iter() {
+            self.find_release_points(*i, range.clone(), range.end, &mut release_points)?;
+        }
+
+        // stream the data into the new base layer (on top of the old layers, so it keeps the deletes of the layers still below it)
+        let mut writer = LayerWriter::new(self.alloc.add_layer()?, self.layers.last().unwrap().timestamp)?;
+        let mut released = vec![false; self.layers.len()];
+        let mut released_space = 0;
+        for (range, i) in extents {
+            let layer = i - released[..i].iter().filter(|x| **x).count();
+            self.merge_extent(0, false, layer, range.clone(), range.start, &mut writer)?;
+
+            if writer.disk_size() <= max_extra_space + released_space { continue };
+
+            // release the layers that nothing after the streamed data depends on, once the base layer covers their data
+            let release = (0..released.len()).filter(|j| !released[*j] && release_points[*j] <= range.end).collect::<Vec<_>>();
+            if release.is_empty() { continue };
+            writer.checkpoint()?;
+            let layers = release.iter().map(|j| j - released[..*j].iter().filter(|x| **x).count()).collect::<Vec<_>>();
+            self.alloc.drop_layers(&layers)?;
+            for layer in layers.into_iter().rev() {
+                released_space += self.layers.remove(layer).disk_size();
+            }
+            for j in release { released[j] = true };
+            self.invalidate(); // the layers above shifted down
+        }
+
+        // release the rest of the old layers
+        let base = writer.finish()?;
+        self.alloc.rebase(self.layers.len())?;
+        self.layers = vec![base];
+        self.invalidate();
+        self.epoch += 1;
+        self.commits = 0;
 
         Ok(())
     }
 
+    /// Rebases just the range of the database; materializes its current data into a new layer
+    /// and strips the range out of all the older layers, leaving the history of the rest of the database untouched
+    pub fn rebase_range(&mut self, range: Range<u64>) -> Result<(), Error> {
+        self.commit_heap()?;
+        self.drop_empty_heap()?;
+        let layers = self.layers.len();
+
+        // materialize the current data of the range
+        let mut writer = LayerWriter::new(self.alloc.add_layer()?, self.layers.last().map(|x| x.timestamp).unwrap_or(0))?;
+        for (r, i) in self.resolve(0..layers, range.clone())?.0 {
+            self.merge_extent(0, true, i, r.clone(), r.start, &mut writer)?; // nothing will be below it once the range is stripped
+        }
+        let mut changed = !writer.is_empty();
+        if changed {
+            self.layers.push(writer.finish()?);
+        } else { // nothing to materialize, so there's no need for the layer
+            drop(writer);
+            self.alloc.drop_top_layer()?;
+        }
+
+        // strip the older layers (from the top down so references still resolve)
+        for i in (0..layers).rev() {
+            changed |= self.strip_layer(i, &range)?;
+        }
+
+        if changed { self.epoch += 1 };
+        Ok(())
+    }
+
+    /// Rewrites the layer without the range (if it has anything in it); references into the range get resolved; returns if the layer was rewritten
+    fn strip_layer(&mut self, layer: usize, range: &Range<u64>) -> Result<bool, Error> {
+        let bounds = if let Some(x) = self.layers[layer].bounds.clone() { x } else { return Ok(false) };
+
+        // find the layer's sections & if any of them have anything to do with the range
+        let mut sections = Vec::new();
+        let mut dirty = false;
+        for r in self.layers[layer].check_collisions(&bounds)?.into_vec() {
+            let src = self.layers[layer].ref_source_unchecked(&r)?;
+            dirty |= r.start < range.end && range.start < r.end
+                || src.is_some_and(|x| x < range.end && range.start < x + (r.end-r.start));
+            sections.push((r, src));
+        }
+        if !dirty { return Ok(false) };
+        sections.sort_unstable_by_key(|(r, _)| r.start);
+
+        // nothing's left of the layer once the range is stripped out of it
+        if sections.iter().all(|(r, _)| range.start <= r.start && r.end <= range.end) {
+            self.alloc.drop_layers(&[layer])?;
+            self.layers.remove(layer);
+            self.invalidate();
+            return Ok(true);
+        }
+
+        let mut writer = LayerWriter::new(self.alloc.add_replacement_layer()?, self.layers[layer].timestamp)?;
+        for (r, src) in sections {
+            // only keep what's outside the range
+            let pieces = [r.start..std::cmp::min(r.end, range.start), std::cmp::max(r.start, range.end)..r.end];
+            for piece in pieces.into_iter().filter(|x| !x.is_empty()) {
+                let src = if let Some(x) = src { x + (piece.start-r.start) } else {
+                    writer.copy_section(&mut self.layers[layer], &piece, piece.start)?;
+                    continue;
+                };
+
+                // resolve the references that point into the range
+                let src_range = src..src + (piece.end-piece.start);
+                let inside = std::cmp::max(src_range.start, range.start)..std::cmp::min(src_range.end, range.end);
+                if inside.is_empty() {
+                    writer.write_section(piece, &SectionData::Ref(src))?;
+                    continue;
+                }
+
+                if src_range.start < inside.start { writer.write_section(piece.start..piece.start + (inside.start-src), &SectionData::Ref(src))? };
+                let (extents, missing) = self.resolve(0..layer, inside.clone())?;
+                let mut parts = extents.into_iter()
+                    .map(|(r, i)| (r, Some(i)))
+                    .chain(missing.into_iter().map(|r| (r, None)))
+                    .collect::<Vec<_>>();
+                parts.sort_unstable_by_key(|(r, _)| r.start);
+                for (r, i) in parts {
+                    let to = r.start - src + piece.start;
+                    match i {
+                        Some(i) => self.merge_extent(0, false, i, r, to, &mut writer)?,
+                        None => writer.write_section(to..to + (r.end-r.start), &SectionData::Tombstone)?,
+                    }
+                }
+                if inside.end < src_range.end { writer.write_section(piece.start + (inside.end-src)..piece.end, &SectionData::Ref(inside.end))? };
+            }
+        }
+
+        let stripped = writer.finish()?;
+        self.alloc.replace_layers(layer..layer+1)?;
+        self.layers[layer] = stripped;
+        self.invalidate();
+        Ok(true)
+    }
+
+    /// Finds the address of the database after which nothing depends on each layer anymore (for the extent and whatever its references point to)
+    fn find_release_points(&mut self, layer: usize, range: Range<u64>, dependant_end: u64, release_points: &mut [u64]) -> Result<(), Error> {
+        release_points[layer] = std::cmp::max(release_points[layer], dependant_end);
+        let src = if let Some(x) = self.layers[layer].ref_source_unchecked(&range)? { x } else { return Ok(()) };
+
+        for (r, i) in self.resolve(0..layer, src..src + (range.end-range.start))?.0 {
+            self.find_release_points(i, r, dependant_end, release_points)?;
+        } Ok(())
+    }
+
     /// Writes data to the heap layer (collisions are fine) (`flush` to commit the heap layers to disk)
     #[inline]
     pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
@@ -127,17 +526,144 @@ impl<'l, A: Allocator<'l>> StackDB<'l, A> {
         Ok(())
     }
 
+    /// Copies the data in the range to the destination address, without re-writing the data itself (through reference sections)
+    pub fn copy(&mut self, src: Range<u64>, dst: u64) -> Result<(), Error> {
+        let layer = self.get_heap_layer()?;
+
+        // uncommitted writes are copied over directly; everything else gets referenced
+        let mut sections = Vec::new();
+        let mut collisions = layer.check_collisions(&src)?;
+        collisions.sort_unstable_by_key(|r| r.start);
+        for range in collisions.iter() {
+            let (r, data) = layer.read_section_unchecked(range)?;
+            sections.push((range.clone(), data.sub(r).into_owned()));
+        }
+        for range in layer.check_non_collisions(&src, &collisions).into_vec() {
+            sections.push((range.clone(), SectionData::Ref(range.start)));
+        }
+
+        for (range, data) in sections {
+            layer.write_section_unchecked(range.start - src.start + dst..range.end - src.start + dst, data)?;
+        } Ok(())
+    }
+
+    /// Moves the data in the range to the destination address (overlapping ranges are fine) and deletes whatever's left of the source range
+    #[inline]
+    pub fn move_range(&mut self, src: Range<u64>, dst: u64) -> Result<(), Error> {
+        self.copy(src.clone(), dst)?;
+
+        // delete the parts of the source that didn't get overwritten
+        let dst = dst..dst + (src.end-src.start);
+        if src.start < dst.start { self.delete(src.start..std::cmp::min(src.end, dst.start))? };
+        if dst.end < src.end { self.delete(std::cmp::max(src.start, dst.end)..src.end)? };
+        Ok(())
+    }
+
+    /// Fills the range with a single repeated byte (takes up a few bytes no matter the size of the range)
+    #[inline]
+    pub fn fill(&mut self, range: Range<u64>, byte: u8) -> Result<(), Error> {
+        self.get_heap_layer()?.write_section_unchecked(range, SectionData::Fill(byte))
+    }
+
+    /// Deletes the data in the range; reads of it will throw an `OutOfBounds` error and rebasing drops it completely
+    #[inline]
+    pub fn delete(&mut self, range: Range<u64>) -> Result<(), Error> {
+        self.get_heap_layer()?.write_section_unchecked(range, SectionData::Tombstone)
+    }
+
+    /// Writes the new data only if the data currently at the address matches what's expected; returns if the swap happened
+    ///
+    /// **note:** unwritten (or deleted) bytes never match
+    #[inline]
+    pub fn compare_and_swap(&mut self, addr: u64, expected: &[u8], new: &[u8]) -> Result<bool, Error> {
+        let current = match self.read(addr..addr + expected.len() as u64) {
+            Err(Error::OutOfBounds) => return Ok(false),
+            x => x?,
+        };
+        if *current != *expected { return Ok(false) };
+        self.write(addr, new)?;
+        Ok(true)
+    }
+
+    /// Creates a (nested) savepoint within the uncommitted writes to later roll back to or release
+    #[inline]
+    pub fn savepoint(&mut self) -> Result<Savepoint, Error> {
+        let savepoint = self.get_heap_layer()?.savepoint()?;
+        Ok(Savepoint(savepoint, self.heap_generation))
+    }
+
+    /// Makes sure the savepoint belongs to the current heap layer
+    #[inline]
+    fn check_savepoint(&self, savepoint: Savepoint) -> Result<(), Error> {
+        if !self.heap_layer || savepoint.1 != self.heap_generation { return Err(Error::InvalidSavepoint) };
+        Ok(())
+    }
+
+    /// Undoes all the uncommitted writes made since the savepoint (keeps the savepoint but drops any newer ones)
+    #[inline]
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Result<(), Error> {
+        self.check_savepoint(savepoint)?;
+        self.layers.last_mut().unwrap().rollback_to(savepoint.0)
+    }
+
+    /// Drops the savepoint (and any newer ones) while keeping the writes made since
+    #[inline]
+    pub fn release(&mut self, savepoint: Savepoint) -> Result<(), Error> {
+        self.check_savepoint(savepoint)?;
+        self.layers.last_mut().unwrap().release(savepoint.0)
+    }
+
     /// Commits / writes the read-write layer's (on the heap) writes to the database (on the disk); making it read-only
+    ///
+    /// **note:** invalidates all savepoints, and compacts the database if the compaction policy says so;
+    /// the commit doesn't depend on the compaction, so a failed one is kept for `take_compaction_error` instead of returned
     #[inline]
     pub fn commit(&mut self) -> Result<(), Error> {
-        if !self.heap_layer { return Ok(()) };
+        if !self.commit_heap()? { return Ok(()) };
+
+        if self.policy.as_ref().is_some_and(|x| x.mode == CompactionMode::Inline) {
+            if let Err(e) = self.compact_if_due() { self.compaction_error = Some(e) };
+        } Ok(())
+    }
+
+    /// Flushes the heap layer (if there's anything to flush) and lets the allocator know it's committed; returns if it did
+    #[inline]
+    fn commit_heap(&mut self) -> Result<bool, Error> {
+        if !self.flush_heap()? { return Ok(false) };
+        self.alloc.layer_committed()?;
+        self.commits += 1;
+        Ok(true)
+    }
+
+    /// Flushes the heap layer to the disk (if there's anything to flush); returns if it did
+    #[inline]
+    fn flush_heap(&mut self) -> Result<bool, Error> {
+        if !self.heap_layer { return Ok(false) };
 
         let layer = self.layers.last_mut().unwrap();
         // Don't flush if layer is empty
-        if layer.bounds.is_none() { return Ok(()) };
+        let bounds = if let Some(x) = layer.bounds.clone() { x } else { return Ok(false) };
         layer.flush()?;
         self.heap_layer = false;
 
+        // the newly committed layer overwrites the resolved map where it has sections
+        if let Some(mut resolved) = self.resolved.take() { // gets rebuilt on error
+            let i = self.layers.len()-1;
+            for range in self.layers[i].check_collisions(&bounds)?.into_vec() {
+                resolved.insert(range, i);
+            } self.resolved = Some(resolved);
+        }
+
+        Ok(true)
+    }
+
+    /// Drops the heap layer if there's nothing in it (so it doesn't get in the way of rewriting the committed layers)
+    #[inline]
+    fn drop_empty_heap(&mut self) -> Result<(), Error> {
+        if !self.heap_layer || self.layers.last().unwrap().bounds.is_some() { return Ok(()) };
+        self.alloc.drop_top_layer()?;
+        self.layers.pop();
+        self.heap_layer = false;
         Ok(())
     }
 }
@@ -1,18 +1,42 @@
 //! Defines the Allocator trait for StackDB
 
-use std::io::{Read, Seek, Write};
-use crate::{base::layer::Layer, errors::Error};
+use std::ops::Range;
+use crate::{base::layer::{Layer, LayerStream}, errors::Error};
 
 /// The allocator for a StackDB that defines how or where the layers are stored and managed
 pub trait Allocator<'l> {
     /// The type of data stream the layers read and write to
-    type LayerStream: Write + Read + Seek;
+    type LayerStream: LayerStream;
     /// Loads all the read-only layers in the database as `Layers`
     fn load_layers(&self) -> Result<Vec<Layer<'l, Self::LayerStream>>, Error>;
     /// Adds a read-write layer to the database
     fn add_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error>;
+    /// Called once the top layer has been committed (for allocators that tidy up their layers on commit)
+    #[inline]
+    fn layer_committed(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// If dropping layers below the top frees their space without rewriting the layers above them (what `StackDB::rebase_streaming` relies on)
+    #[inline]
+    fn releases_in_place(&self) -> bool {
+        true
+    }
     /// Removes the top layer from the database
     fn drop_top_layer(&mut self) -> Result<(), Error>;
+    /// Removes a layer (at any position) from the database
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error>;
+    /// Removes the layers (at any positions, as of before any of them got removed) from the database
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        let mut layers = layers.to_vec();
+        layers.sort_unstable_by(|a, b| b.cmp(a)); // from the top down so the positions don't shift
+        for layer in layers {
+            self.drop_layer(layer)?;
+        } Ok(())
+    }
     /// Removes all the bottom layers except for the one specified (and above)
     fn rebase(&mut self, top_layer: usize) -> Result<(), Error>;
+    /// Adds a detached read-write layer that isn't part of the database until it replaces a run of layers (see `replace_layers`)
+    fn add_replacement_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error>;
+    /// Replaces the run of layers with the last replacement layer added (at the same position in the database)
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error>;
  }
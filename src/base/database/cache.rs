@@ -0,0 +1,126 @@
+//! A bounded LRU cache of the raw bytes read from the committed layers of the database
+
+use std::collections::{BTreeMap, HashMap};
+use crate::errors::Error;
+use super::{allocator::Allocator, StackDB};
+
+/// The size of the pages of the layer streams that get cached
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The layer & position in its stream of a cached page
+type PageKey = (usize, u64);
+
+/// The hit/miss statistics & memory usage of the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The amount of page reads served from the cache
+    pub hits: u64,
+    /// The amount of page reads that went to the layer stream
+    pub misses: u64,
+    /// The bytes currently held by the cache
+    pub size: usize,
+    /// The maximum bytes the cache may hold
+    pub budget: usize,
+}
+
+/// A cache of layer stream pages keyed by the layer & the page's position in its stream; the least recently used pages get evicted first
+#[derive(Debug, Default)]
+pub struct Cache {
+    /// The cached pages & when they were last used
+    pages: HashMap<PageKey, (Box<[u8]>, u64)>,
+    /// The cached pages ordered by when they were last used
+    recency: BTreeMap<u64, PageKey>,
+    /// The logical clock of page uses
+    tick: u64,
+    /// The statistics of the cache
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// Grabs a cached page (marking it as recently used)
+    #[inline]
+    fn get(&mut self, key: PageKey) -> Option<&[u8]> {
+        let (page, used) = self.pages.get_mut(&key)?;
+        self.recency.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(page)
+    }
+
+    /// Adds a page to the cache, evicting the least recently used pages to stay within the budget
+    #[inline]
+    fn insert(&mut self, key: PageKey, page: Box<[u8]>) {
+        if page.len() > self.stats.budget { return };
+        self.tick += 1;
+        self.stats.size += page.len();
+        self.recency.insert(self.tick, key);
+        if let Some((old, used)) = self.pages.insert(key, (page, self.tick)) {
+            self.stats.size -= old.len();
+            self.recency.remove(&used);
+        }
+
+        while self.stats.size > self.stats.budget {
+            let (_, key) = self.recency.pop_first().unwrap();
+            self.stats.size -= self.pages.remove(&key).unwrap().0.len();
+        }
+    }
+
+    /// Drops every cached page (the statistics are kept)
+    #[inline]
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+        self.stats.size = 0;
+    }
+}
+
+impl<'l, A: Allocator<'l>> StackDB<'l, A> {
+    /// Sets the memory budget (in bytes) of the cache of committed layer data; zero disables it
+    #[inline]
+    pub fn set_cache_budget(&mut self, budget: usize) {
+        self.cache.stats.budget = budget;
+        if self.cache.stats.size > budget { self.cache.clear() };
+    }
+
+    /// Grabs the hit/miss statistics & memory usage of the cache
+    #[inline]
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats
+    }
+
+    /// Reads raw bytes at the position in a committed layer's stream, through the cache
+    pub(super) fn read_cached(&mut self, layer: usize, pos: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        if self.cache.stats.budget == 0 { return self.layers[layer].read_raw(pos, buffer) };
+
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let at = pos + filled as u64;
+            let (page, offset) = (at - at % PAGE_SIZE, (at % PAGE_SIZE) as usize);
+
+            if self.cache.get((layer, page)).is_some() {
+                self.cache.stats.hits += 1;
+            } else {
+                self.cache.stats.misses += 1;
+                let data = self.layers[layer].read_page(page, PAGE_SIZE as usize)?;
+                self.cache.insert((layer, page), data);
+            }
+
+            // pages bigger than the budget never get cached
+            let len = match self.cache.pages.get(&(layer, page)) {
+                Some((data, _)) => {
+                    let len = std::cmp::min(data.len().saturating_sub(offset), buffer.len() - filled);
+                    if len == 0 { return Err(Error::DBCorrupt(Box::new(Error::InvalidLayer))) };
+                    buffer[filled..filled + len].copy_from_slice(&data[offset..offset + len]);
+                    len
+                },
+                None => {
+                    let len = std::cmp::min(PAGE_SIZE as usize - offset, buffer.len() - filled);
+                    self.layers[layer].read_raw(at, &mut buffer[filled..filled + len])?;
+                    len
+                },
+            };
+            filled += len;
+        } Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! Policies for automatically compacting the layers of the database
+
+use std::{sync::{Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
+use crate::errors::Error;
+use super::{allocator::Allocator, StackDB};
+
+/// Where the automatic compaction runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompactionMode {
+    /// Right after the commit that crossed a threshold
+    #[default]
+    Inline,
+    /// On a background thread (see `spawn_compactor`); commits never compact
+    Background,
+}
+
+/// The thresholds that trigger the automatic compaction of all the committed layers into one once crossed
+#[derive(Debug, Clone, Default)]
+pub struct CompactionPolicy {
+    /// The maximum amount of committed layers
+    pub max_layers: Option<usize>,
+    /// The maximum space amplification; the stored bytes that are overwritten (dead) over the stored bytes that are visible (live)
+    ///
+    /// **note:** checking it walks through every layer
+    pub max_space_amplification: Option<f64>,
+    /// The maximum amount of commits since the last compaction
+    pub max_commits: Option<usize>,
+    /// Where the compaction runs
+    pub mode: CompactionMode,
+}
+
+impl<'l, A: Allocator<'l>> StackDB<'l, A> {
+    /// Sets (or removes) the policy for automatically compacting the database
+    #[inline]
+    pub fn set_compaction_policy(&mut self, policy: Option<CompactionPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Checks if any of the compaction policy's thresholds have been crossed
+    pub fn compaction_due(&mut self) -> Result<bool, Error> {
+        let policy = if let Some(x) = &self.policy { x } else { return Ok(false) };
+        let layers = self.layers.len() - self.heap_layer as usize;
+        if layers < 2 { return Ok(false) }; // nothing to compact
+
+        if policy.max_layers.is_some_and(|x| layers > x)
+        || policy.max_commits.is_some_and(|x| self.commits > x) {
+            return Ok(true);
+        }
+
+        if let Some(max) = policy.max_space_amplification {
+            return Ok(self.stats()?.space_amplification() > max);
+        }
+
+        Ok(false)
+    }
+
+    /// Takes the error of the last inline compaction that failed after its commit went through (if there was one)
+    #[inline]
+    pub fn take_compaction_error(&mut self) -> Option<Error> {
+        self.compaction_error.take()
+    }
+
+    /// Compacts all the committed layers into one if the compaction policy's thresholds have been crossed; returns if it did
+    pub fn compact_if_due(&mut self) -> Result<bool, Error> {
+        if !self.compaction_due()? { return Ok(false) };
+        self.compact(0..self.layers.len() - self.heap_layer as usize)?;
+        Ok(true)
+    }
+}
+
+/// Spawns a thread that checks the database's compaction policy every interval and compacts it when due;
+/// the thread stops once the database is dropped
+pub fn spawn_compactor<A>(db: &Arc<Mutex<StackDB<'static, A>>>, interval: Duration) -> JoinHandle<Result<(), Error>>
+where
+    A: Allocator<'static> + Send + 'static,
+    A::LayerStream: Send,
+{
+    let db = Arc::downgrade(db);
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let db = if let Some(x) = db.upgrade() { x } else { return Ok(()) };
+        db.lock()
+            .map_err(|_| Error::Custom("database lock poisoned".into()))?
+            .compact_if_due()?;
+    })
+}
@@ -0,0 +1,70 @@
+//! A maintained map of which committed layer each extent of the database belongs to
+
+use std::{collections::BTreeMap, ops::Range};
+use super::Extent;
+
+/// The resolved view of the committed layers; maps the start of each extent to its end & the layer that holds it
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedMap {
+    extents: BTreeMap<u64, (u64, usize)>,
+}
+
+impl ResolvedMap {
+    /// Builds the map from the ordered, non-overlapping extents of the committed layers
+    #[inline]
+    pub fn new(extents: Vec<Extent>) -> Self {
+        Self {
+            extents: extents.into_iter().map(|(r, i)| (r.start, (r.end, i))).collect(),
+        }
+    }
+
+    /// The amount of extents in the map
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.extents.len()
+    }
+
+    /// If there are no extents in the map
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.extents.is_empty()
+    }
+
+    /// Marks the range as belonging to the layer; overwriting whatever it belonged to before
+    pub fn insert(&mut self, range: Range<u64>, layer: usize) {
+        if range.is_empty() { return };
+
+        // cut the range out of the extents it overlaps
+        let overlapped = self.extents.range(..range.start).next_back()
+            .filter(|(_, (end, _))| *end > range.start)
+            .map(|(start, _)| *start)
+            .into_iter()
+            .chain(self.extents.range(range.start..range.end).map(|(start, _)| *start))
+            .collect::<Vec<_>>();
+        for start in overlapped {
+            let (end, i) = self.extents.remove(&start).unwrap();
+            if start < range.start { self.extents.insert(start, (range.start, i)); };
+            if range.end < end { self.extents.insert(range.end, (end, i)); };
+        }
+
+        self.extents.insert(range.start, (range.end, layer));
+    }
+
+    /// Finds which layer each part of the range belongs to; returns the ordered extents and the parts that no layer covers
+    pub fn get(&self, range: &Range<u64>) -> (Vec<Extent>, Vec<Range<u64>>) {
+        let mut extents = Vec::new();
+        let mut missing = Vec::new();
+        let mut cursor = range.start;
+
+        let first = self.extents.range(..range.start).next_back().filter(|(_, (end, _))| *end > range.start);
+        for (start, (end, i)) in first.into_iter().chain(self.extents.range(range.start..range.end)) {
+            let from = std::cmp::max(*start, range.start);
+            if cursor < from { missing.push(cursor..from) };
+            cursor = std::cmp::min(*end, range.end);
+            extents.push((from..cursor, *i));
+        }
+
+        if cursor < range.end { missing.push(cursor..range.end) };
+        (extents, missing)
+    }
+}
@@ -0,0 +1,42 @@
+//! Retention policies for thinning out the history of the database
+
+use std::time::Duration;
+use crate::errors::Error;
+use super::{allocator::Allocator, StackDB};
+
+/// The rules for which commits (layers) to keep when thinning out the history of the database;
+/// everything in between the kept commits gets merged into them
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// The amount of the latest commits to keep
+    pub keep_last: usize,
+    /// The period (e.g. a day) of which to keep one snapshot (the last commit of the period) for the older history
+    pub snapshot_interval: Option<Duration>,
+}
+
+impl<'l, A: Allocator<'l>> StackDB<'l, A> {
+    /// Thins out the history of the database by merging the committed layers in between the ones kept by the retention policy (the latest commit is always kept)
+    pub fn retain(&mut self, policy: &RetentionPolicy) -> Result<(), Error> {
+        let layers = self.layers.len() - self.heap_layer as usize;
+        let recent = layers.saturating_sub(std::cmp::max(policy.keep_last, 1));
+
+        // find the commits to keep
+        let mut kept = Vec::new();
+        if let Some(interval) = policy.snapshot_interval {
+            let interval = std::cmp::max(interval.as_secs(), 1);
+            for i in 0..recent {
+                // the last commit of each period
+                if i+1 == recent || self.layers[i].timestamp / interval != self.layers[i+1].timestamp / interval {
+                    kept.push(i);
+                }
+            }
+        } kept.extend(recent..layers);
+
+        // merge each kept commit with the discarded ones below it (from the top down so the indexes don't shift)
+        let mut end = layers;
+        for start in kept.into_iter().rev().skip(1).map(|x| x+1).chain([0]) {
+            self.compact(start..end)?;
+            end = start;
+        } Ok(())
+    }
+}
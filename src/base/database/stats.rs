@@ -0,0 +1,117 @@
+//! Space accounting of the database and its layers
+
+use std::ops::Range;
+use crate::errors::Error;
+use super::{allocator::Allocator, hull, merge, Extent, StackDB};
+
+/// The space accounting of a single layer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayerStats {
+    /// The total size of all the writes in the layer
+    pub size: u64,
+    /// The bounds of the layer; the range of the layer
+    pub bounds: Option<Range<u64>>,
+    /// The time the layer got committed (seconds since the unix epoch); zero if it hasn't been yet
+    pub timestamp: u64,
+    /// The bytes of the layer that are still visible (directly or through references)
+    pub live: u64,
+    /// The bytes of the layer that are shadowed by higher layers
+    pub shadowed: u64,
+    /// The bytes the sections of the layer take up in storage (fills, references & tombstones are tiny no matter their range)
+    pub stored: u64,
+    /// The stored bytes of the still visible parts of the layer (partly shadowed sections are split by how much of them is visible)
+    pub stored_live: u64,
+    /// The stored bytes of the shadowed parts of the layer
+    pub stored_shadowed: u64,
+}
+
+/// The space accounting of the whole database
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The stats of each layer (bottom layer first, heap layer last)
+    pub layers: Vec<LayerStats>,
+    /// The total size of all the writes in the database
+    pub size: u64,
+    /// The bytes of the database that are still visible
+    pub live: u64,
+    /// The bytes of the database that are shadowed (dead)
+    pub shadowed: u64,
+    /// The bytes the sections of the database take up in storage
+    pub stored: u64,
+    /// The stored bytes of the still visible parts of the database
+    pub stored_live: u64,
+    /// The stored bytes of the shadowed (dead) parts of the database
+    pub stored_shadowed: u64,
+}
+
+impl Stats {
+    /// The stored bytes that are shadowed (dead) over the stored bytes that are live
+    #[inline]
+    pub fn space_amplification(&self) -> f64 {
+        self.stored_shadowed as f64 / self.stored_live as f64
+    }
+}
+
+impl<'l, A: Allocator<'l>> StackDB<'l, A> {
+    /// Reports the size, bounds, live and shadowed bytes (both logical & stored) of every layer and the database as a whole
+    ///
+    /// **note:** walks through every layer
+    pub fn stats(&mut self) -> Result<Stats, Error> {
+        let mut live = vec![Vec::new(); self.layers.len()];
+        if let Some(bounds) = hull(&self.layers) {
+            let extents = self.resolve(0..self.layers.len(), bounds)?.0;
+            self.mark_live(extents, &mut live)?;
+        }
+
+        let mut stats = Stats::default();
+        for (layer, live) in self.layers.iter_mut().zip(live) {
+            // references may point at the same bytes more than once
+            let live = merge(live);
+            let live_size = live.iter().map(|r| r.end - r.start).sum::<u64>();
+
+            // the stored bytes of each section are split by how much of it is live
+            let (mut stored, mut stored_live, mut j) = (0, 0, 0);
+            for section in layer.sections()? {
+                let (range, data) = section?;
+                let size = data.stored_size(&range);
+                while j < live.len() && live[j].end <= range.start { j += 1 };
+                let visible = live[j..].iter()
+                    .take_while(|r| r.start < range.end)
+                    .map(|r| std::cmp::min(r.end, range.end) - std::cmp::max(r.start, range.start))
+                    .sum::<u64>();
+                stored += size;
+                stored_live += (size as u128 * visible as u128 / (range.end-range.start) as u128) as u64;
+            }
+
+            stats.size += layer.size;
+            stats.live += live_size;
+            stats.shadowed += layer.size - live_size;
+            stats.stored += stored;
+            stats.stored_live += stored_live;
+            stats.stored_shadowed += stored - stored_live;
+            stats.layers.push(LayerStats {
+                size: layer.size,
+                bounds: layer.bounds.clone(),
+                timestamp: layer.timestamp,
+                live: live_size,
+                shadowed: layer.size - live_size,
+                stored,
+                stored_live,
+                stored_shadowed: stored - stored_live,
+            });
+        }
+
+        Ok(stats)
+    }
+
+    /// Marks the extents (and whatever their references point to) as live
+    fn mark_live(&mut self, extents: Vec<Extent>, live: &mut [Vec<Range<u64>>]) -> Result<(), Error> {
+        for (range, i) in extents {
+            if let Some(src) = self.layers[i].ref_source_unchecked(&range)? {
+                let extents = self.resolve(0..i, src..src + (range.end-range.start))?.0;
+                self.mark_live(extents, live)?;
+            }
+            live[i].push(range);
+        } Ok(())
+    }
+}
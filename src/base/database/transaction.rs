@@ -0,0 +1,96 @@
+//! Optimistic transactions that get checked for conflicts upon commit
+
+use std::{borrow::Cow, io::Cursor, ops::Range};
+use crate::{base::layer::Layer, errors::Error};
+use super::{allocator::Allocator, StackDB};
+
+/// A range read by a transaction; with the amount of layers in the database at the time
+/// and the bytes read if there was a heap layer (as the heap layer can still get written to after the read)
+type TxRead = (Range<u64>, usize, Option<Box<[u8]>>);
+
+/// An optimistic transaction on a `StackDB` that buffers its own writes and records the ranges it reads.
+///
+/// Upon commit, the transaction fails with a `Conflict` error if any write (committed or not) made after a range was read touched it
+#[derive(Debug)]
+pub struct Transaction {
+    /// The rebase epoch of the database when the transaction started
+    epoch: u64,
+    /// The ranges of the database read by the transaction & the version they were read at
+    reads: Vec<TxRead>,
+    /// The buffered writes of the transaction
+    writes: Layer<'static, Cursor<Vec<u8>>>,
+}
+
+impl Transaction {
+    /// Reads data from the transaction's own writes or the database (and records the read)
+    pub fn read<'l, A: Allocator<'l>>(&mut self, db: &mut StackDB<'l, A>, addr: Range<u64>) -> Result<Box<[u8]>, Error> {
+        let mut data = vec![0u8; (addr.end-addr.start) as usize].into_boxed_slice();
+
+        // read the transaction's own writes first
+        let mut collisions = self.writes.check_collisions(&addr)?;
+        collisions.sort_unstable_by_key(|r| r.start);
+        for range in collisions.iter() {
+            let read = self.writes.read_unchecked(range)?;
+            data[(range.start-addr.start) as usize..(range.end-addr.start) as usize].copy_from_slice(&read.1[read.0]);
+        }
+
+        // then the database
+        for range in self.writes.check_non_collisions(&addr, &collisions).into_vec() {
+            let read = db.read(range.clone())?;
+            data[(range.start-addr.start) as usize..(range.end-addr.start) as usize].copy_from_slice(&read);
+            self.reads.push((range, db.layers.len(), db.heap_layer.then(|| read.clone())));
+        }
+
+        Ok(data)
+    }
+
+    /// Buffers a write within the transaction
+    #[inline]
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
+        self.writes.write_unchecked(addr, Cow::Owned(data.to_vec()))
+    }
+}
+
+impl<'l, A: Allocator<'l>> StackDB<'l, A> {
+    /// Starts an optimistic transaction on the database
+    #[inline]
+    pub fn transaction(&self) -> Transaction {
+        Transaction {
+            epoch: self.epoch,
+            reads: Vec::new(),
+            writes: Layer::new(Cursor::new(Vec::new())),
+        }
+    }
+
+    /// Checks the transaction for conflicts and commits its writes to the database
+    ///
+    /// **note:** also commits any other uncommitted writes on the database, and throws a `Conflict` error if any of them touch what the transaction read
+    pub fn commit_transaction(&mut self, mut tx: Transaction) -> Result<(), Error> {
+        // the layers got rewritten, so there's no telling what changed
+        if tx.epoch != self.epoch { return Err(Error::Conflict) };
+
+        for (range, layers, heap) in tx.reads.iter() {
+            // check the layers newer than the read for any writes to the range
+            for layer in self.layers.iter_mut().skip(*layers) {
+                if !layer.check_collisions(range)?.is_empty() {
+                    return Err(Error::Conflict);
+                }
+            }
+
+            // the heap layer at the time of the read (committed or not) could've been written to since
+            if heap.as_ref().is_some_and(|x| **x != *self.read(range.clone()).unwrap_or_default()) {
+                return Err(Error::Conflict);
+            }
+        }
+
+        // move the transaction's writes into the database
+        if let Some(bounds) = tx.writes.bounds.clone() {
+            for range in tx.writes.check_collisions(&bounds)?.iter() {
+                let read = tx.writes.read_unchecked(range)?;
+                self.write(range.start, &read.1[read.0])?;
+            }
+        }
+
+        self.commit()
+    }
+}
@@ -1,23 +1,150 @@
 //! A layer/frame of which gets *stacked* to form the database
 pub mod mapper;
 
-use std::{borrow::Cow, io::{BufWriter, Read, Seek, Write}, ops::Range};
+use std::{borrow::Cow, fs::File, io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write}, ops::Range, time::{SystemTime, UNIX_EPOCH}};
 use crate::errors::Error;
-use mapper::Mapper;
+use mapper::{LazyData, Mapper, MapperIter, SavepointState};
 
-pub type Section<'l> = (Range<u64>, Cow<'l, [u8]>);
+pub type Section<'l> = (Range<u64>, SectionData<'l>);
+
+/// The data held by a section of a layer
+#[derive(Debug, Clone)]
+pub enum SectionData<'l> {
+    /// The raw bytes written to the section
+    Bytes(Cow<'l, [u8]>),
+    /// A reference to the data at an address in the database (as seen from the layers *below* this one)
+    Ref(u64),
+    /// A deletion of the data in the section's range (a hole in the database)
+    Tombstone,
+    /// The section's range filled with a single repeated byte
+    Fill(u8),
+}
+
+impl<'l> SectionData<'l> {
+    /// Grabs a sub-range of the section's data (without borrowing from the section)
+    #[inline]
+    pub fn sub(&self, range: Range<usize>) -> SectionData<'l> {
+        match self {
+            Self::Bytes(Cow::Borrowed(x)) => SectionData::Bytes(Cow::Borrowed(&x[range])),
+            Self::Bytes(Cow::Owned(x)) => SectionData::Bytes(Cow::Owned(x[range].to_vec())),
+            Self::Ref(addr) => SectionData::Ref(addr + range.start as u64),
+            Self::Tombstone => SectionData::Tombstone,
+            Self::Fill(byte) => SectionData::Fill(*byte),
+        }
+    }
+
+    /// Borrows the section's data (without copying any bytes)
+    #[inline]
+    pub fn borrowed(&self) -> SectionData<'_> {
+        match self {
+            Self::Bytes(x) => SectionData::Bytes(Cow::Borrowed(x)),
+            Self::Ref(addr) => SectionData::Ref(*addr),
+            Self::Tombstone => SectionData::Tombstone,
+            Self::Fill(byte) => SectionData::Fill(*byte),
+        }
+    }
+
+    /// Takes ownership of the section's data
+    #[inline]
+    pub fn into_owned(self) -> SectionData<'static> {
+        match self {
+            Self::Bytes(x) => SectionData::Bytes(Cow::Owned(x.into_owned())),
+            Self::Ref(addr) => SectionData::Ref(addr),
+            Self::Tombstone => SectionData::Tombstone,
+            Self::Fill(byte) => SectionData::Fill(byte),
+        }
+    }
+
+    /// The size of the section's data on disk
+    #[inline]
+    fn disk_size(&self) -> u64 {
+        match self {
+            Self::Bytes(x) => x.len() as u64,
+            Self::Ref(_) => 8,
+            Self::Tombstone => 0,
+            Self::Fill(_) => 1,
+        }
+    }
+}
+
+/// The on-disk tag of a section holding raw bytes
+const SECTION_BYTES: u8 = 0;
+/// The on-disk tag of a section referencing another address
+const SECTION_REF: u8 = 1;
+/// The on-disk tag of a deleted section
+const SECTION_TOMBSTONE: u8 = 2;
+/// The on-disk tag of a section filled with a single byte
+const SECTION_FILL: u8 = 3;
+
+/// The stream (file, buffer, etc.) that a layer lives in
+pub trait LayerStream: Write + Read + Seek {
+    /// The whole contents of the stream if they can be borrowed straight from memory (for reads without copying)
+    #[inline]
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+impl LayerStream for File {}
+
+impl<T: AsRef<[u8]>> LayerStream for Cursor<T> where Cursor<T>: Write {
+    #[inline]
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self.get_ref().as_ref())
+    }
+}
+
+/// A coarse bitmap of which of the (equal) parts of a layer's bounds have any sections in them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage(pub [u64; COVERAGE_WORDS]);
+
+impl Coverage {
+    /// Coverage of the whole bounds (it never skips anything)
+    pub const FULL: Self = Self([u64::MAX; COVERAGE_WORDS]);
+
+    /// Builds the coverage of the layer bounds from its (ordered) section ranges
+    pub fn new<'a>(bounds: &Range<u64>, sections: impl IntoIterator<Item = &'a Range<u64>>) -> Self {
+        let mut coverage = Self([0; COVERAGE_WORDS]);
+        for range in sections.into_iter().filter(|x| !x.is_empty()) {
+            for bit in Self::part(bounds, range.start)..=Self::part(bounds, range.end-1) {
+                coverage.0[bit / 64] |= 1 << (bit % 64);
+            }
+        } coverage
+    }
+
+    /// Checks if any of the parts of the bounds that the range touches have sections in them
+    #[inline]
+    pub fn overlaps(&self, bounds: &Range<u64>, range: &Range<u64>) -> bool {
+        let range = std::cmp::max(range.start, bounds.start)..std::cmp::min(range.end, bounds.end);
+        if range.is_empty() { return false };
+        (Self::part(bounds, range.start)..=Self::part(bounds, range.end-1)).any(|bit| self.0[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Finds which part of the bounds an address is in
+    #[inline]
+    fn part(bounds: &Range<u64>, addr: u64) -> usize {
+        let span = (bounds.end - bounds.start) as u128;
+        ((addr - bounds.start) as u128 * (COVERAGE_WORDS as u128 * 64) / span) as usize
+    }
+}
 
 /// Represents a layer (either in the heap or disk) in the stack-db that *stacks*
 #[derive(Debug)]
-pub struct Layer<'l, Stream: Write + Read + Seek> {
+pub struct Layer<'l, Stream: LayerStream> {
     /// The bounds of the layer; the range of the layer
     pub bounds: Option<Range<u64>>,
     /// The mapper that maps to either the heap or disk
     mapper: Mapper<'l>,
     /// The total size of all the writes in the layer
     pub size: u64,
-    /// The current read cursor to speed up sequential reads
-    pub read_cursor: (u64, usize),
+    /// The length of the layer's sections on disk
+    len: u64,
+    /// The time the layer got committed (seconds since the unix epoch); zero if it hasn't been yet
+    pub timestamp: u64,
+    /// Which parts of the bounds have sections in them (always full on the heap)
+    pub coverage: Coverage,
+    /// The current read cursor to speed up sequential reads; the start address & stream position of the last section read from disk
+    pub read_cursor: (u64, u64),
     /// The underlying file reader/writer
     stream: Stream,
 }
@@ -34,69 +161,145 @@ fn get_u64(buffer: &[u8], range: Range<usize>) -> Result<u64, Error> {
     ))
 }
 
-/// used for error handling in iterators
-#[inline]
-fn until_err<T, E>(err: &mut &mut Result<(), E>, item: Result<T, E>) -> Option<T> {
-    match item {
-        Ok(item) => Some(item),
-        Err(e) => {
-            **err = Err(e);
-            None
+/// Finds the section of the mapper that the address range is within (from the read cursor) and the relative range within the section
+fn locate_section<'l, Stream: Write + Read + Seek>(mapper: &'l Mapper<'_>, stream: &mut Stream, len: u64, read_cursor: &mut (u64, u64), addr: &Range<u64>) -> Result<(Range<usize>, LazyData<'l>), Error> {
+    let mut iter = mapper.iter(stream, len, seek_from(*read_cursor, addr.start), addr.start)?;
+    loop {
+        let offset = iter.offset();
+        let (r, x) = match iter.next() { Some(x) => x?, None => return Err(Error::OutOfBounds) };
+        if r.start > addr.start { return Err(Error::OutOfBounds) }; // sections are ordered
+        if addr.end <= r.end { // read must be equal to or within layer section
+            *read_cursor = (r.start, offset);
+            return Ok(((addr.start-r.start) as usize..(addr.end-r.start) as usize, x));
         }
     }
 }
 
-impl<'l,  Stream: Write + Read + Seek> Layer<'l, Stream> {
+/// Finds where in the stream to start looking for the address; continues from the read cursor unless the read jumps backwards
+#[inline]
+fn seek_from(read_cursor: (u64, u64), addr: u64) -> u64 {
+    if read_cursor.0 <= addr { read_cursor.1 } else { REWIND_IDX }
+}
+
+impl<'l, Stream: LayerStream> Layer<'l, Stream> {
     #[inline]
     pub fn new(stream: Stream) -> Self {
         Self {
             bounds: None,
             mapper: Mapper::new(),
             size: 0,
-            read_cursor: (0, 0),
+            len: 0,
+            timestamp: 0,
+            coverage: Coverage::FULL,
+            read_cursor: (0, REWIND_IDX),
             stream,
         }
     }
 
+    /// Swaps the underlying stream of the layer for one that wraps it (keeping the layer's state)
+    #[inline]
+    pub fn map_stream<T: LayerStream>(self, wrap: impl FnOnce(Stream) -> T) -> Layer<'l, T> {
+        Layer {
+            bounds: self.bounds,
+            mapper: self.mapper,
+            size: self.size,
+            len: self.len,
+            timestamp: self.timestamp,
+            coverage: self.coverage,
+            read_cursor: self.read_cursor,
+            stream: wrap(self.stream),
+        }
+    }
+
     #[inline]
     pub fn load(mut stream: Stream) -> Result<Self, Error> {
-        let mut buffer = [0u8; (u64::BITS as usize/8) * 3]; // buffer for three `u64` values: `size`, `bounds.start`, `bounds.end`
-        match stream.read_exact(&mut buffer) {
-            Ok(_) => (),
-            Err(_) => return Err(Error::DBCorrupt(Box::new(Error::InvalidLayer))),
-        };
+        let mut buffer = [0u8; REWIND_IDX as usize]; // buffer for the magic & version, five `u64` values: `size`, `bounds.start`, `bounds.end`, `len`, `timestamp` and the coverage bitmap
+        let (magic, header) = buffer.split_at_mut(LAYER_MAGIC.len());
+        if stream.read_exact(magic).is_err() { return Err(Error::DBCorrupt(Box::new(Error::InvalidLayer))) };
 
+        // a layer of another format (or not a layer at all) can't be read, however short it is
+        if *magic != LAYER_MAGIC { return Err(Error::InvalidLayer) };
+        if stream.read_exact(header).is_err() { return Err(Error::DBCorrupt(Box::new(Error::InvalidLayer))) };
 
         // read metadata; return corruption error if failure
-        let size = get_u64(&buffer, 0..8)?;
-        let bounds = get_u64(&buffer, 8..16)?..get_u64(&buffer, 16..24)?;
+        let size = get_u64(&buffer, 8..16)?;
+        let bounds = get_u64(&buffer, 16..24)?..get_u64(&buffer, 24..32)?;
+        let len = get_u64(&buffer, 32..40)?;
+        let timestamp = get_u64(&buffer, 40..48)?;
+        let mut coverage = Coverage([0; COVERAGE_WORDS]);
+        for (i, word) in coverage.0.iter_mut().enumerate() {
+            *word = get_u64(&buffer, 48+i*8..56+i*8)?;
+        }
 
         Ok(Self {
-            bounds: Some(bounds),
+            bounds: (len > 0).then_some(bounds), // an empty layer has no bounds
             mapper: Mapper::Disk,
             size,
-            read_cursor: (0, 0),
+            len,
+            timestamp,
+            coverage,
+            read_cursor: (0, REWIND_IDX),
             stream,
         })
     }
 
+    /// Reads a layer from before layers had a format version (a header of `size`, `bounds.start` & `bounds.end` followed by untagged sections of raw bytes)
+    /// into a heap layer over the stream, with the later sections overwriting the earlier ones like they used to; `flush` it to write it in the current format
+    pub fn load_legacy(mut legacy: impl Read, stream: Stream) -> Result<Self, Error> {
+        let corrupt = |_| Error::DBCorrupt(Box::new(Error::InvalidLayer));
+        let mut buffer = [0u8; 8 * 3]; // buffer for three `u64` values: `size`, `bounds.start` & `bounds.end`
+        legacy.read_exact(&mut buffer).map_err(corrupt)?;
+        if buffer[..LAYER_MAGIC.len()-1] == LAYER_MAGIC[..LAYER_MAGIC.len()-1] { return Err(Error::InvalidLayer) }; // a layer with a format version
+        let size = get_u64(&buffer, 0..8)?;
+
+        let mut layer = Self::new(stream);
+        let mut read = 0;
+        while read < size {
+            let mut bounds = [0u8; 8 * 2]; // buffer for two `u64` values: `bounds.start` & `bounds.end`
+            legacy.read_exact(&mut bounds).map_err(corrupt)?;
+            let start = get_u64(&bounds, 0..8)?;
+            let len = get_u64(&bounds, 8..16)?.checked_sub(start).ok_or(Error::DBCorrupt(Box::new(Error::InvalidLayer)))?;
+            if len > size - read { return Err(Error::DBCorrupt(Box::new(Error::InvalidLayer))) };
+
+            let mut data = vec![0u8; len as usize];
+            legacy.read_exact(&mut data).map_err(corrupt)?;
+            layer.write_unchecked(start, Cow::Owned(data))?;
+            read += len;
+        } Ok(layer)
+    }
+
+    /// The size of the (committed) layer on disk
+    #[inline]
+    pub fn disk_size(&self) -> u64 {
+        REWIND_IDX + self.len
+    }
+
+    /// Lazily iterates over the sections of the layer; yielding their bounds & data (raw bytes on disk are left unread as their stream position)
+    #[inline]
+    pub fn sections(&mut self) -> Result<MapperIter<'_, '_, Stream>, Error> {
+        self.mapper.iter(&mut self.stream, self.len, REWIND_IDX, 0)
+    }
+
     /// Checks for collisions on the current layer
     #[inline]
     pub fn check_collisions(&mut self, range: &Range<u64>) -> Result<Box<[Range<u64>]>, Error> {
         // if range not even in bounds or layer empty; return 
         match self.bounds.as_ref() {
-            Some(bounds) => if bounds.end < range.start || range.end < bounds.start { return Ok(Box::new([])) },
+            Some(bounds) => if !self.coverage.overlaps(bounds, range) { return Ok(Box::new([])) },
             None => return Ok(Box::new([])),
         }
         
-        let mut err = Ok(());
-        let out = self.mapper.iter(&mut self.stream, self.size, REWIND_IDX)?
-            .scan(&mut err, until_err) // handles the errors
-            .filter(|(r, _)| range.start < r.end && r.start < range.end)
-            .map(|(r, _)| range.start.max(r.start)..std::cmp::min(range.end, r.end))
-            .collect();
-        err?;
-        Ok(out)
+        let mut iter = self.mapper.iter(&mut self.stream, self.len, seek_from(self.read_cursor, range.start), range.start)?;
+        let mut out = Vec::new();
+        loop {
+            let offset = iter.offset();
+            let (r, _) = match iter.next() { Some(x) => x?, None => break };
+            if r.start >= range.end { break }; // sections are ordered
+            if range.start < r.end {
+                if out.is_empty() { self.read_cursor = (r.start, offset) }; // so reads of the collisions continue from the first one
+                out.push(range.start.max(r.start)..std::cmp::min(range.end, r.end));
+            }
+        } Ok(out.into_boxed_slice())
     }
 
     /// Takes in the **ordered** output of the `check_collisions` function to find the inverse
@@ -119,45 +322,123 @@ impl<'l,  Stream: Write + Read + Seek> Layer<'l, Stream> {
     /// Reads from the layer unchecked and returns the section data and the desired relative range within the section.
     ///
     /// **warning:** will throw `out-of-bounds` error (or undefined behaviour) if the read is accross two sections *(each read can only be on one section of a layer)*
+    ///
+    /// **note:** deleted sections also throw an `out-of-bounds` error, and so do reference sections as they can't be resolved within a single layer; use `read_section_unchecked` and resolve them through the layers below (like `StackDB::read` does)
     #[inline]
-    pub fn read_unchecked(&mut self, addr: &Range<u64>) -> Result<(Range<usize>, Cow<[u8]>), Error> {
-        let mut err = Ok(());
-        let out = self.mapper.iter(&mut self.stream, self.size, REWIND_IDX)? // todo: Actually use the read-cursor so that you don't have to iterate through everything to get to where you want
-            .scan(&mut err, until_err) // handles errors
-            .find(|(r, _)| r.start <= addr.start && addr.end <= r.end) // read must be equal to or within layer section
-            .map(|(r, x)| ((addr.start-r.start) as usize..(addr.end-r.start) as usize, x));
-        err?;
-        out
-            .map(Ok)
-            .unwrap_or(Err(Error::OutOfBounds))
+    pub fn read_unchecked(&mut self, addr: &Range<u64>) -> Result<(Range<usize>, Cow<'_, [u8]>), Error> {
+        match self.read_section_unchecked(addr)? {
+            (range, SectionData::Bytes(data)) => Ok((range, data)),
+            (range, SectionData::Fill(byte)) => Ok((0..range.len(), Cow::Owned(vec![byte; range.len()]))), // only fill what's read
+            (_, SectionData::Ref(_) | SectionData::Tombstone) => Err(Error::OutOfBounds),
+        }
     }
 
-    /// Writes to the heap layer without checking for collisions
+    /// Reads the section (of any kind) from the layer unchecked and returns its data and the desired relative range within the section.
     ///
-    /// **WARNING:** the layer will be corrupt (due to undefined behaviour) if there are any collisions; this function is meant to be used internally
+    /// **warning:** will throw `out-of-bounds` error (or undefined behaviour) if the read is accross two sections *(each read can only be on one section of a layer)*
+    #[inline]
+    pub fn read_section_unchecked(&mut self, addr: &Range<u64>) -> Result<(Range<usize>, SectionData<'_>), Error> {
+        match locate_section(&self.mapper, &mut self.stream, self.len, &mut self.read_cursor, addr)? {
+            (range, LazyData::Loaded(x)) => Ok((range, x)),
+            (range, LazyData::OnDisk(pos)) => { // only read the part of the raw bytes that's wanted
+                if self.stream.as_slice().is_some() { // borrowed straight from memory
+                    let data = self.stream.as_slice().unwrap().get(pos as usize + range.start..pos as usize + range.end).ok_or(Error::DBCorrupt(Box::new(Error::InvalidLayer)))?;
+                    return Ok((0..data.len(), SectionData::Bytes(Cow::Borrowed(data))));
+                }
+
+                let mut data = vec![0u8; range.len()];
+                self.stream.seek(SeekFrom::Start(pos + range.start as u64))?;
+                self.stream.read_exact(&mut data)?;
+                Ok((0..data.len(), SectionData::Bytes(Cow::Owned(data))))
+            },
+        }
+    }
+
+    /// Finds the section (of any kind) of the layer without reading its raw bytes from disk and returns its data and the desired relative range within the section.
+    ///
+    /// **warning:** will throw `out-of-bounds` error (or undefined behaviour) if the read is accross two sections *(each read can only be on one section of a layer)*
+    pub fn locate_section_unchecked(&mut self, addr: &Range<u64>) -> Result<(Range<usize>, LazyData<'_>), Error> {
+        locate_section(&self.mapper, &mut self.stream, self.len, &mut self.read_cursor, addr)
+    }
+
+    /// Finds where the part of the section points to if it's a reference section (without reading any raw bytes)
+    ///
+    /// **warning:** will throw `out-of-bounds` error (or undefined behaviour) if the read is accross two sections *(each read can only be on one section of a layer)*
+    #[inline]
+    pub fn ref_source_unchecked(&mut self, addr: &Range<u64>) -> Result<Option<u64>, Error> {
+        match self.locate_section_unchecked(addr)? {
+            (r, LazyData::Loaded(SectionData::Ref(src))) => Ok(Some(src + r.start as u64)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads raw bytes from the position in the layer's stream
+    #[inline]
+    pub fn read_raw(&mut self, pos: u64, buffer: &mut [u8]) -> Result<(), Error> {
+        if let Some(x) = self.stream.as_slice() {
+            buffer.copy_from_slice(x.get(pos as usize..pos as usize + buffer.len()).ok_or(Error::DBCorrupt(Box::new(Error::InvalidLayer)))?);
+            return Ok(());
+        }
+
+        self.stream.seek(SeekFrom::Start(pos))?;
+        self.stream.read_exact(buffer)?;
+        Ok(())
+    }
+
+    /// Reads up to `size` raw bytes from the position in the layer's stream (less at the end of the stream)
+    #[inline]
+    pub fn read_page(&mut self, pos: u64, size: usize) -> Result<Box<[u8]>, Error> {
+        let mut data = Vec::with_capacity(size);
+        self.stream.seek(SeekFrom::Start(pos))?;
+        (&mut self.stream).take(size as u64).read_to_end(&mut data)?;
+        Ok(data.into_boxed_slice())
+    }
+
+    /// Writes to the heap layer, overwriting any older writes to the same range within the layer
+    ///
+    /// **note:** unchecked as in it doesn't check for collisions with *other* layers; this function is meant to be used internally
     #[inline]
     pub fn write_unchecked(&mut self, idx: u64, data: Cow<'l, [u8]>) -> Result<(), Error> {
+        self.write_section_unchecked(idx..idx+data.len() as u64, SectionData::Bytes(data))
+    }
+
+    /// Writes a section (of any kind) to the heap layer, overwriting any older writes to the same range within the layer
+    ///
+    /// **warning:** the layer will be corrupt if the range doesn't match the length of the section data
+    pub fn write_section_unchecked(&mut self, range: Range<u64>, data: SectionData<'l>) -> Result<(), Error> {
         // cannot write on read-only
-        let (mapper, write_cursor) = self.mapper.get_writer()?;
-        let range = idx..idx+data.len() as u64;
-
-        // get the idx ni the map to insert to
-        let map_idx = if write_cursor.0 == idx {
-            write_cursor.1
-        } else {
-            mapper
-                .iter()
-                .enumerate()
-                .find(|(_, (r, _))| r.start > idx)
-                .map(|(i, _)| i)
-                .unwrap_or(0) // if map is empty write to the first index
-        };
+        let writer = self.mapper.get_writer()?;
+        if range.is_empty() { return Ok(()) };
+
+        // get the sections in the map that the write overlaps; a sequential write can't overlap the section before it
+        let mut overlapped = Vec::new();
+        if *writer.write_cursor != range.start {
+            overlapped.extend(writer.mapper.range(..range.start).next_back().filter(|(_, (r, _))| r.end > range.start).map(|(k, _)| *k));
+        } overlapped.extend(writer.mapper.range(range.start..range.end).map(|(k, _)| *k));
+
+        // cut the write out of the overlapped sections
+        let mut overwritten = Vec::with_capacity(overlapped.len());
+        for key in overlapped {
+            let (r, section) = writer.mapper.remove(&key).unwrap();
+            let from = std::cmp::max(r.start, range.start);
+            let to = std::cmp::min(r.end, range.end);
+
+            if r.start < from { writer.mapper.insert(r.start, (r.start..from, section.sub(0..(from-r.start) as usize))); };
+            overwritten.push((from..to, section.sub((from-r.start) as usize..(to-r.start) as usize)));
+            if to < r.end { writer.mapper.insert(to, (to..r.end, section.sub((to-r.start) as usize..(r.end-r.start) as usize))); };
+        }
 
         // insert data into the map and update write cursor & size
-        mapper.insert(map_idx, (range.clone(), data));
-        *write_cursor = (range.end, map_idx+1);
+        writer.mapper.insert(range.start, (range.clone(), data));
+        *writer.write_cursor = range.end;
+        self.size -= overwritten.iter().map(|(r, _)| r.end - r.start).sum::<u64>();
         self.size += range.end - range.start;
 
+        // only keep undo information if it could be rolled back to
+        if !writer.savepoints.is_empty() {
+            writer.undo.push((range.clone(), overwritten));
+        }
+
         // Update bounds
         self.bounds = Some(match self.bounds {
             Some(ref x) => std::cmp::min(x.start, range.start)..std::cmp::max(x.end, range.end),
@@ -167,10 +448,55 @@ impl<'l,  Stream: Write + Read + Seek> Layer<'l, Stream> {
         Ok(())
     }
 
+    /// Creates a savepoint within the heap layer to later roll back to (or release) and returns its index
+    #[inline]
+    pub fn savepoint(&mut self) -> Result<usize, Error> {
+        let writer = self.mapper.get_writer()?;
+        writer.savepoints.push(SavepointState {
+            undo_len: writer.undo.len(),
+            size: self.size,
+            bounds: self.bounds.clone(),
+        });
+        Ok(writer.savepoints.len()-1)
+    }
+
+    /// Undoes all the writes to the heap layer since the savepoint; the savepoint stays active but any newer ones are dropped
+    pub fn rollback_to(&mut self, savepoint: usize) -> Result<(), Error> {
+        let writer = self.mapper.get_writer()?;
+        let state = writer.savepoints.get(savepoint).ok_or(Error::InvalidSavepoint)?.clone();
+        writer.savepoints.truncate(savepoint+1);
+
+        // undo the writes in reverse order
+        while writer.undo.len() > state.undo_len {
+            let (range, overwritten) = writer.undo.pop().unwrap();
+            // later writes may have split the write into pieces under other keys
+            let pieces = writer.mapper.range(range.start..range.end).map(|(k, _)| *k).collect::<Vec<_>>();
+            for key in pieces { writer.mapper.remove(&key); };
+            writer.mapper.extend(overwritten.into_iter().map(|section| (section.0.start, section)));
+        }
+
+        *writer.write_cursor = 0;
+        self.size = state.size;
+        self.bounds = state.bounds;
+
+        Ok(())
+    }
+
+    /// Drops the savepoint (and any newer ones) while keeping all the writes made since
+    #[inline]
+    pub fn release(&mut self, savepoint: usize) -> Result<(), Error> {
+        let writer = self.mapper.get_writer()?;
+        if savepoint >= writer.savepoints.len() { return Err(Error::InvalidSavepoint) };
+        writer.savepoints.truncate(savepoint);
+
+        // no more savepoints to roll back to
+        if writer.savepoints.is_empty() {
+            writer.undo.clear();
+        } Ok(())
+    }
+
     /// Moves the layer from the **heap** to **disk**
     pub fn flush(&mut self) -> Result<(), Error> {
-        const BUFFER_SIZE: usize = 1024 * 1024 * 4; // 4MiB buffer size
-        
         // don't flush if it's an empty layer or in read-only mode
         let (bounds, mapper) = if let (Some(b), Mapper::Heap { mapper, .. }) = (&self.bounds, &self.mapper) { (b, mapper) } else {  return Ok(()) };
         let mut file = BufWriter::with_capacity(BUFFER_SIZE, &mut self.stream);
@@ -178,24 +504,204 @@ impl<'l,  Stream: Write + Read + Seek> Layer<'l, Stream> {
         // write from the start
         file.rewind()?;
 
-        // write the bounds & size of the layer
-        file.write_all(&self.size.to_be_bytes())?;
-        file.write_all(&bounds.start.to_be_bytes())?;
-        file.write_all(&bounds.end.to_be_bytes())?;
+        // write the bounds, size, length & commit time of the layer
+        let len = mapper.values().map(|(_, data)| SECTION_HEADER + data.disk_size()).sum::<u64>();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+        let coverage = Coverage::new(bounds, mapper.values().map(|(r, _)| r));
+        write_header(&mut file, self.size, bounds, len, timestamp, &coverage)?;
 
-        // we assume that the map is already sorted
-        for (range, data) in mapper {
-            file.write_all(&range.start.to_be_bytes())?;
-            file.write_all(&range.end.to_be_bytes())?;
-            file.write_all(data)?;
+        // the map is ordered by start address
+        for (range, data) in mapper.values() {
+            write_section(&mut file, range, data)?;
         }
 
         // flush file and switch to disk layer
         file.flush()?;
         self.mapper = Mapper::Disk;
+        self.read_cursor = (0, REWIND_IDX);
+        self.len = len;
+        self.timestamp = timestamp;
+        self.coverage = coverage;
         
         Ok(())
     }
 }
 
-pub const REWIND_IDX: u64 = 8 + 8 + 8; // skip the `u64`s: `layer_size`, `layer_bound.start` and `layer_bound.end`
+/// Writes a read-only layer straight to its stream section by section, without holding the sections on the heap
+pub struct LayerWriter<Stream: LayerStream> {
+    /// The underlying (buffered) file writer
+    stream: BufWriter<Stream>,
+    /// The bounds of the sections written so far
+    bounds: Option<Range<u64>>,
+    /// The total size of the sections written so far
+    size: u64,
+    /// The length of the sections written so far on disk
+    len: u64,
+    /// The commit time of the layer
+    timestamp: u64,
+    /// The merged ranges of the sections written so far (for the coverage of the layer)
+    extents: Vec<Range<u64>>,
+}
+
+impl<Stream: LayerStream> LayerWriter<Stream> {
+    /// Starts writing over a new (empty) layer with its commit time (seconds since the unix epoch)
+    #[inline]
+    pub fn new(layer: Layer<'_, Stream>, timestamp: u64) -> Result<Self, Error> {
+        Self::with_capacity(layer, timestamp, BUFFER_SIZE)
+    }
+
+    /// Starts writing over a new (empty) layer with its commit time, buffering up to `capacity` bytes of it at a time
+    pub fn with_capacity(layer: Layer<'_, Stream>, timestamp: u64, capacity: usize) -> Result<Self, Error> {
+        let mut stream = BufWriter::with_capacity(capacity, layer.stream);
+        stream.rewind()?;
+        write_header(&mut stream, 0, &(0..0), 0, timestamp, &Coverage::FULL)?; // placeholder until finished
+
+        Ok(Self {
+            stream,
+            bounds: None,
+            size: 0,
+            len: 0,
+            timestamp,
+            extents: Vec::new(),
+        })
+    }
+
+    /// Writes a section to the end of the layer
+    ///
+    /// **warning:** the layer will be corrupt if the sections aren't written in order or overlap
+    pub fn write_section(&mut self, range: Range<u64>, data: &SectionData) -> Result<(), Error> {
+        if range.is_empty() { return Ok(()) };
+        write_section(&mut self.stream, &range, data)?;
+        self.track(range, data.disk_size());
+        Ok(())
+    }
+
+    /// Copies (the part of) a section of another layer to the end of the layer at the destination address;
+    /// raw bytes on disk get copied over in chunks instead of being read into memory all at once
+    ///
+    /// **warning:** the layer will be corrupt if the sections aren't written in order or overlap, and the read must be within a single section (like `read_section_unchecked`)
+    pub fn copy_section<S: LayerStream>(&mut self, layer: &mut Layer<'_, S>, addr: &Range<u64>, dst: u64) -> Result<(), Error> {
+        let range = dst..dst + (addr.end-addr.start);
+        let pos = match layer.locate_section_unchecked(addr)? {
+            (r, LazyData::OnDisk(pos)) => pos + r.start as u64,
+            (r, LazyData::Loaded(data)) => return self.write_section(range, &data.sub(r)),
+        };
+        if range.is_empty() { return Ok(()) };
+
+        self.stream.write_all(&range.start.to_be_bytes())?;
+        self.stream.write_all(&range.end.to_be_bytes())?;
+        self.stream.write_all(&[SECTION_BYTES])?;
+        let size = range.end - range.start;
+        let mut buffer = vec![0u8; std::cmp::min(size, BUFFER_SIZE as u64) as usize];
+        let mut copied = 0;
+        while copied < size {
+            let chunk = &mut buffer[..std::cmp::min(size - copied, BUFFER_SIZE as u64) as usize];
+            layer.read_raw(pos + copied, chunk)?;
+            self.stream.write_all(chunk)?;
+            copied += chunk.len() as u64;
+        }
+
+        self.track(range, size);
+        Ok(())
+    }
+
+    /// Keeps track of the size, length, extents & bounds of the layer after a section (of the size on disk) got written
+    #[inline]
+    fn track(&mut self, range: Range<u64>, disk_size: u64) {
+        self.size += range.end - range.start;
+        self.len += SECTION_HEADER + disk_size;
+        match self.extents.last_mut() {
+            Some(x) if x.end == range.start => x.end = range.end,
+            _ => self.extents.push(range.clone()),
+        }
+        self.bounds = Some(match self.bounds {
+            Some(ref x) => x.start..range.end,
+            None => range,
+        });
+    }
+
+    /// The size of the layer on disk so far
+    #[inline]
+    pub fn disk_size(&self) -> u64 {
+        REWIND_IDX + self.len
+    }
+
+    /// If no sections have been written yet
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_none()
+    }
+
+    /// Makes the sections written so far a valid layer in the stream (while more sections can still be written after them)
+    #[inline]
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        self.publish()?;
+        self.stream.seek(SeekFrom::Start(self.disk_size()))?;
+        Ok(())
+    }
+
+    /// Writes the header of the sections written so far (after them) & flushes the stream; returns the bounds & coverage of the layer
+    fn publish(&mut self) -> Result<(Range<u64>, Coverage), Error> {
+        let bounds = self.bounds.clone().unwrap_or(0..0);
+        let coverage = Coverage::new(&bounds, &self.extents);
+        self.stream.rewind()?; // flushes the sections first
+        write_header(&mut self.stream, self.size, &bounds, self.len, self.timestamp, &coverage)?;
+        self.stream.flush()?;
+        Ok((bounds, coverage))
+    }
+
+    /// Finishes writing the layer and returns it as a read-only layer
+    pub fn finish<'l>(mut self) -> Result<Layer<'l, Stream>, Error> {
+        let (bounds, coverage) = self.publish()?;
+
+        Ok(Layer {
+            bounds: self.bounds.is_some().then_some(bounds), // an empty layer has no bounds
+            mapper: Mapper::Disk,
+            size: self.size,
+            len: self.len,
+            timestamp: self.timestamp,
+            coverage,
+            read_cursor: (0, REWIND_IDX),
+            stream: self.stream.into_inner().map_err(|e| e.into_error())?,
+        })
+    }
+}
+
+/// Writes the layer metadata to the start of a layer
+#[inline]
+fn write_header(file: &mut impl Write, size: u64, bounds: &Range<u64>, len: u64, timestamp: u64, coverage: &Coverage) -> Result<(), Error> {
+    file.write_all(&LAYER_MAGIC)?;
+    file.write_all(&size.to_be_bytes())?;
+    file.write_all(&bounds.start.to_be_bytes())?;
+    file.write_all(&bounds.end.to_be_bytes())?;
+    file.write_all(&len.to_be_bytes())?;
+    file.write_all(&timestamp.to_be_bytes())?;
+    for word in coverage.0 {
+        file.write_all(&word.to_be_bytes())?;
+    } Ok(())
+}
+
+/// Writes a single section of a layer
+#[inline]
+fn write_section(file: &mut impl Write, range: &Range<u64>, data: &SectionData) -> Result<(), Error> {
+    file.write_all(&range.start.to_be_bytes())?;
+    file.write_all(&range.end.to_be_bytes())?;
+    match data {
+        SectionData::Bytes(x) => {
+            file.write_all(&[SECTION_BYTES])?;
+            file.write_all(x)?;
+        },
+        SectionData::Ref(addr) => {
+            file.write_all(&[SECTION_REF])?;
+            file.write_all(&addr.to_be_bytes())?;
+        },
+        SectionData::Tombstone => file.write_all(&[SECTION_TOMBSTONE])?,
+        SectionData::Fill(byte) => file.write_all(&[SECTION_FILL, *byte])?,
+    } Ok(())
+}
+
+pub const REWIND_IDX: u64 = LAYER_MAGIC.len() as u64 + 8 + 8 + 8 + 8 + 8 + COVERAGE_WORDS as u64 * 8; // skip the magic & the `u64`s: `layer_size`, `layer_bound.start`, `layer_bound.end`, `layer_len`, `layer_timestamp` and the coverage bitmap
+const LAYER_MAGIC: [u8; 8] = *b"SKDBLYR\x01"; // the first bytes of a layer; the last one is the version of the layer format
+const COVERAGE_WORDS: usize = 4; // 256 parts of the layer bounds
+const SECTION_HEADER: u64 = 8 + 8 + 1; // the `u64`s: `section_bound.start` & `section_bound.end` and the `u8` section tag
+pub(crate) const BUFFER_SIZE: usize = 1024 * 1024 * 4; // 4MiB buffer size
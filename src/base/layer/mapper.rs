@@ -1,30 +1,74 @@
 //! The mapper of the layer that can either live on the **heap** or **disk**
 
-use std::{borrow::Cow, io::{Read, Seek, Write}};
+use std::{collections::{btree_map, BTreeMap}, io::{Read, Seek, SeekFrom, Write}, ops::Range};
 use crate::{base::layer::get_u64, errors::Error};
-use super::{Section, REWIND_IDX};
+use super::{Section, SectionData, REWIND_IDX, SECTION_HEADER, SECTION_BYTES, SECTION_REF, SECTION_TOMBSTONE, SECTION_FILL};
 
 /// The mapper that holds all the writes to the layer and their location mapping in the database
 #[derive(Debug)]
 pub enum Mapper<'l> {
     /// A **read-write** version of the mapper on the **heap**
     Heap {
-        /// The current write cursor to speed up sequential qrites
-        write_cursor: (u64, usize),
-        /// *self explainitory*
-        mapper: Vec<Section<'l>>,
+        /// The end of the last write to speed up sequential writes
+        write_cursor: u64,
+        /// The sections of the layer keyed by their start address
+        mapper: BTreeMap<u64, Section<'l>>,
+        /// The undo log of the writes since the oldest savepoint; the written range & the section fragments it overwrote
+        undo: Vec<(Range<u64>, Vec<Section<'l>>)>,
+        /// The active savepoints (oldest first)
+        savepoints: Vec<SavepointState>,
     },
     /// A **read-only** version of the mapper on the **disk**
     Disk,
 }
 
+/// The state of the heap layer at a savepoint
+#[derive(Debug, Clone)]
+pub struct SavepointState {
+    /// The length of the undo log at the savepoint
+    pub undo_len: usize,
+    /// The size of the layer at the savepoint
+    pub size: u64,
+    /// The bounds of the layer at the savepoint
+    pub bounds: Option<Range<u64>>,
+}
+
+/// A mutable view into the internal heap representation of the mapper
+pub struct HeapWriter<'a, 'l> {
+    pub mapper: &'a mut BTreeMap<u64, Section<'l>>,
+    pub write_cursor: &'a mut u64,
+    pub undo: &'a mut Vec<(Range<u64>, Vec<Section<'l>>)>,
+    pub savepoints: &'a mut Vec<SavepointState>,
+}
+
+/// The data of a section yielded by `MapperIter`; raw bytes on disk are left unread until they're needed
+#[derive(Debug, Clone)]
+pub enum LazyData<'l> {
+    /// Data that's already in memory (heap sections & the small kinds of disk sections)
+    Loaded(SectionData<'l>),
+    /// Raw bytes left on disk at the stream position
+    OnDisk(u64),
+}
+
+impl LazyData<'_> {
+    /// The bytes the section (of the range) takes up in its layer's stream; including its header
+    #[inline]
+    pub fn stored_size(&self, range: &Range<u64>) -> u64 {
+        SECTION_HEADER + match self {
+            Self::Loaded(x) => x.disk_size(),
+            Self::OnDisk(_) => range.end - range.start,
+        }
+    }
+}
+
 /// A read-only iterator of the mapper that can live on either the heap or disk 
-pub struct MapperIter<'l, Stream: Write + Read + Seek> {
+pub struct MapperIter<'l, 's, Stream: Write + Read + Seek> {
     mapper: &'l Mapper<'l>,
-    stream: &'l mut Stream,
-    size: u64,
-    /// the index in the mapper
-    idx: usize,
+    stream: &'s mut Stream,
+    /// the length of the layer's sections on disk
+    len: u64,
+    /// the remaining sections of the heap mapper
+    heap: Option<btree_map::Range<'l, u64, Section<'l>>>,
     /// the **actual** location in the layer
     cursor: u64,
 }
@@ -41,34 +85,49 @@ impl<'l> Mapper<'l> {
     #[inline]
     pub fn new() -> Self {
         Self::Heap {
-            write_cursor: (0, 0),
-            mapper: Vec::new(),
+            write_cursor: 0,
+            mapper: BTreeMap::new(),
+            undo: Vec::new(),
+            savepoints: Vec::new(),
         }
     }
 
     /// Grabs the internal heap representation; if on disk, throw the `ReadOnly` error
     #[inline]
-    pub fn get_writer(&mut self) -> Result<(&mut Vec<Section<'l>>, &mut (u64, usize)), Error> {
-        if let Self::Heap { write_cursor, mapper } = self {
-            Ok((mapper, write_cursor))
+    pub fn get_writer(&mut self) -> Result<HeapWriter<'_, 'l>, Error> {
+        if let Self::Heap { write_cursor, mapper, undo, savepoints } = self {
+            Ok(HeapWriter { mapper, write_cursor, undo, savepoints })
         } else {
             Err(Error::ReadOnly)
         }
     }
 
-    /// Generates an iterator over the interal mapper, from the stream, size and layer read cursor position
-    pub fn iter<'a, Stream: Read + Write + Seek>(&'a self, stream: &'a mut Stream, size: u64, cursor: u64) -> Result<MapperIter<'a, Stream>, Error> {
-        stream.seek(std::io::SeekFrom::Start(cursor))?;
+    /// Generates an iterator over the interal mapper, from the stream, length of the layer sections and layer read cursor position
+    ///
+    /// **note:** heap mappers skip straight to the section containing (or after) the `from` address
+    pub fn iter<'a, 's, Stream: Read + Write + Seek>(&'a self, stream: &'s mut Stream, len: u64, cursor: u64, from: u64) -> Result<MapperIter<'a, 's, Stream>, Error> {
+        stream.seek(SeekFrom::Start(cursor))?;
         Ok(MapperIter {
             mapper: self,
             stream,
-            size,
-            idx: 0,
+            len,
+            heap: if let Self::Heap { mapper, .. } = self {
+                let start = mapper.range(..=from).next_back().filter(|(_, (r, _))| r.end > from).map(|(k, _)| *k).unwrap_or(from);
+                Some(mapper.range(start..))
+            } else { None },
             cursor: cursor - REWIND_IDX,
         })
     }
 }
 
+impl<Stream: Write + Read + Seek> MapperIter<'_, '_, Stream> {
+    /// The position in the stream of the next section (only meaningful on disk)
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.cursor + REWIND_IDX
+    }
+}
+
 /// for unwrapping results within a function that returns an optional result concisely
 macro_rules! optres {
     ($expr:expr) => {
@@ -79,37 +138,51 @@ macro_rules! optres {
     }
 }
 
-impl<'l, Stream: Write + Read + Seek> Iterator for MapperIter<'l, Stream> {
-    type Item = Result<Section<'l>, Error>;
+impl<'l, Stream: Write + Read + Seek> Iterator for MapperIter<'l, '_, Stream> {
+    type Item = Result<(Range<u64>, LazyData<'l>), Error>;
 
-    fn next(&mut self) -> Option<Self::Item> { // probably not a issue but, it loads the entire layer section into memory
+    fn next(&mut self) -> Option<Self::Item> {
         Some(Ok(match self.mapper {
-            Mapper::Heap { mapper, .. } => {
-                if self.idx == mapper.len() { return None };
-                let out = mapper[self.idx].clone();
-                self.idx += 1;
-                out
+            Mapper::Heap { .. } => {
+                let (range, data) = self.heap.as_mut()?.next()?.1;
+                (range.clone(), LazyData::Loaded(data.borrowed())) // heap sections are lent out instead of copied
             },
             Mapper::Disk => {
                 // check for end of layer
-                if self.cursor == self.size { return None };
+                if self.cursor == self.len { return None };
                 
-                // read bounds
-                let mut buffer = [0u8; (u64::BITS as usize/8) * 2]; // buffer for two `u64` values: `bounds.start` & `bounds.end`
+                // read bounds & tag
+                let mut buffer = [0u8; (u64::BITS as usize/8) * 2 + 1]; // buffer for two `u64` values: `bounds.start` & `bounds.end` and the `u8` tag
                 match self.stream.read_exact(&mut buffer) {
                     Ok(_) => (),
                     Err(_) => return Some(Err(Error::DBCorrupt(Box::new(Error::InvalidLayer)))),
                 }
 
                 let bounds = optres!(get_u64(&buffer, 0..8))..optres!(get_u64(&buffer, 8..16));
-
-                // load layer section data into the heap
                 let size = if let Some(x) = bounds.end.checked_sub(bounds.start) { x } else {return Some(Err(Error::DBCorrupt(Box::new(Error::InvalidLayer)))) };
-                let mut data = vec![0u8; size as usize];
-                optres!(self.stream.read_exact(&mut data));
 
-                self.cursor += size;
-                (bounds, Cow::Owned(data))
+                // load the small kinds of section data & skip over raw bytes
+                let (data, disk_size) = match buffer[16] {
+                    SECTION_BYTES => {
+                        optres!(self.stream.seek(SeekFrom::Current(size as i64)));
+                        (LazyData::OnDisk(self.offset() + buffer.len() as u64), size)
+                    },
+                    SECTION_REF => {
+                        let mut addr = [0u8; u64::BITS as usize/8];
+                        optres!(self.stream.read_exact(&mut addr));
+                        (LazyData::Loaded(SectionData::Ref(u64::from_be_bytes(addr))), 8)
+                    },
+                    SECTION_TOMBSTONE => (LazyData::Loaded(SectionData::Tombstone), 0),
+                    SECTION_FILL => {
+                        let mut byte = [0u8];
+                        optres!(self.stream.read_exact(&mut byte));
+                        (LazyData::Loaded(SectionData::Fill(byte[0])), 1)
+                    },
+                    _ => return Some(Err(Error::DBCorrupt(Box::new(Error::InvalidLayer)))),
+                };
+
+                self.cursor += buffer.len() as u64 + disk_size;
+                (bounds, data)
             },
         }))
     }
@@ -1,3 +1,5 @@
 //! Some common & default implementations of `stack-db` to get you up and running quicker
 
 pub mod alloc;
+pub mod manifest;
+pub mod middleware;
@@ -1,141 +1,1129 @@
 //! Some default `stack-db` allocator implementations
 
-use std::{fs::{self, File}, io::Cursor, path::{Path, PathBuf}};
-use crate::{base::{database::allocator::Allocator, layer::Layer}, errors::Error};
+use std::{collections::HashMap, fs::{self, File}, io::{Cursor, Read, Seek, SeekFrom, Write}, ops::Range, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use crate::{base::{database::allocator::Allocator, layer::{Layer, LayerStream}}, errors::Error};
+use super::manifest::{Manifest, MANIFEST};
+
+/// The shared committed contents of an in-memory layer
+type MemSlot = Arc<Mutex<Arc<[u8]>>>;
+
+/// The contents of an in-memory layer stream
+#[derive(Debug)]
+enum MemBuffer {
+    /// Being written to
+    Writing(Vec<u8>),
+    /// Committed (shared with the allocator)
+    Committed(Arc<[u8]>),
+}
+
+/// # In-Memory Layer Stream
+/// ---
+/// A layer buffer that gets shared with its `SkdbMemAlloc` once it's flushed (committed)
+#[derive(Debug)]
+pub struct MemStream {
+    /// the contents of the layer
+    buffer: MemBuffer,
+    /// the position of the stream
+    pos: u64,
+    /// where the committed contents get published to the allocator
+    slot: MemSlot,
+}
+impl MemStream {
+    /// Creates a stream over the committed contents of a slot
+    #[inline]
+    fn new(slot: MemSlot) -> Result<Self, Error> {
+        let data = slot.lock().map_err(|_| Error::Custom("poisoned in-memory layer lock".into()))?.clone();
+        Ok(Self { buffer: MemBuffer::Committed(data), pos: 0, slot })
+    }
+}
+impl Read for MemStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.as_slice().unwrap().get(self.pos as usize..).unwrap_or(&[]);
+        let len = std::cmp::min(data.len(), buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+impl Write for MemStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let MemBuffer::Committed(x) = &self.buffer { self.buffer = MemBuffer::Writing(x.to_vec()) }; // copy on write
+        let MemBuffer::Writing(data) = &mut self.buffer else { unreachable!() };
+
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(self.pos);
+        let len = cursor.write(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let MemBuffer::Writing(x) = &mut self.buffer {
+            let data: Arc<[u8]> = std::mem::take(x).into();
+            *self.slot.lock().map_err(|_| std::io::Error::other("poisoned in-memory layer lock"))? = data.clone();
+            self.buffer = MemBuffer::Committed(data);
+        } Ok(())
+    }
+}
+impl Seek for MemStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(x) => x,
+            SeekFrom::End(x) => (self.as_slice().unwrap().len() as u64).checked_add_signed(x).ok_or(std::io::ErrorKind::InvalidInput)?,
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x).ok_or(std::io::ErrorKind::InvalidInput)?,
+        }; Ok(self.pos)
+    }
+}
+impl LayerStream for MemStream {
+    #[inline]
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(match &self.buffer {
+            MemBuffer::Writing(x) => x,
+            MemBuffer::Committed(x) => x,
+        })
+    }
+}
 
 /// # In-Memory Allocator
 /// ---
-/// For a redis-like database for caching, testing the database, etc. Lives only on the heap and gets wiped on program exit.
-pub struct SkdbMemAlloc;
+/// For a redis-like database for caching, testing the database, etc. Lives only on the heap,
+/// but its committed layers can be saved & restored (in the same layer format as the other allocators)
+#[derive(Debug, Default)]
+pub struct SkdbMemAlloc {
+    /// the committed contents of the layers in the database (bottom layer first)
+    layers: Vec<MemSlot>,
+    /// the contents of the replacement layer (if there is one)
+    replacement: Option<MemSlot>,
+}
+impl SkdbMemAlloc {
+    /// Creates a new (empty) in-memory SkDB
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grabs the committed contents of all the layers (uncommitted layers are empty), without anything a torn flush left past their end
+    fn committed(&self) -> Result<Vec<Arc<[u8]>>, Error> {
+        self.layers.iter()
+            .map(|x| x.lock().map(|x| x.clone()).map_err(|_| Error::Custom("poisoned in-memory layer lock".into())))
+            .filter(|x| x.as_ref().map(|x| !x.is_empty()).unwrap_or(true))
+            .map(|x| {
+                let data = x?;
+                let size = Layer::load(MemStream { buffer: MemBuffer::Committed(data.clone()), pos: 0, slot: MemSlot::default() })?.disk_size() as usize;
+                Ok(if size < data.len() { data[..size].into() } else { data })
+            })
+            .collect()
+    }
+
+    /// Saves the committed layers as a single buffer (in the same format as a `SkdbFileAlloc` file)
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.committed()?.concat())
+    }
+
+    /// Restores the layers saved with `to_bytes` (or read from a `SkdbFileAlloc` file)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let all: Arc<[u8]> = bytes.into();
+        let mut layers = Vec::new();
+        let mut start = 0;
+        while start < bytes.len() {
+            let stream = MemStream { buffer: MemBuffer::Committed(all.clone()), pos: start as u64, slot: MemSlot::default() };
+            let size = Layer::load(stream)?.disk_size() as usize;
+            let data = bytes.get(start..start + size).ok_or(Error::DBCorrupt(Box::new(Error::InvalidLayer)))?;
+            layers.push(Arc::new(Mutex::new(data.into())));
+            start += size;
+        } Ok(Self { layers, replacement: None })
+    }
+
+    /// Exports the committed layers to a new directory database that can be loaded with `SkdbDirAlloc`
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut dir = SkdbDirAlloc::new(path)?;
+        for data in self.committed()? {
+            dir.create_layer()?.write_all(&data)?;
+        } Ok(())
+    }
+}
 impl<'a> Allocator<'a> for SkdbMemAlloc {
-    type LayerStream = Cursor<Vec<u8>>;
+    type LayerStream = MemStream;
     #[inline]
     fn load_layers(&self) -> Result<Vec<Layer<'a, Self::LayerStream>>, Error> {
-        Ok(Vec::new())
+        self.layers.iter().map(|x| Layer::load(MemStream::new(x.clone())?)).collect()
     }
     #[inline]
     fn add_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
-        Ok(Layer::new(Cursor::new(Vec::new())))
+        let slot = MemSlot::default();
+        self.layers.push(slot.clone());
+        Ok(Layer::new(MemStream::new(slot)?))
     }
     #[inline]
     fn drop_top_layer(&mut self) -> Result<(), Error> {
+        self.layers.pop();
+        Ok(())
+    }
+    #[inline]
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        if layer < self.layers.len() { self.layers.remove(layer); };
         Ok(())
     }
     #[inline]
-    fn rebase(&mut self, _: usize) -> Result<(), Error> {
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        self.layers.drain(..std::cmp::min(top_layer, self.layers.len()));
+        Ok(())
+    }
+    #[inline]
+    fn add_replacement_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        let slot = MemSlot::default();
+        self.replacement = Some(slot.clone());
+        Ok(Layer::new(MemStream::new(slot)?))
+    }
+    #[inline]
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        if layers.is_empty() || layers.end > self.layers.len() { return Err(Error::OutOfBounds) };
+        let replacement = self.replacement.take().ok_or(Error::OutOfBounds)?;
+        self.layers.splice(layers, [replacement]);
         Ok(())
     }
 }
 
 /// # Directory Allocator
 /// ---
-/// Allocates within a directory that lives on the file-system with the layer order determined by the layer file names
+/// Allocates within a directory that lives on the file-system, with the order of the layers (and the tags & settings of the database) recorded in its manifest
 pub struct SkdbDirAlloc {
     /// the path of the directory database
     pub path: PathBuf,
-    /// the (sorted) paths of the layers in the database
-    pub layers: Vec<PathBuf>,
-    pub cursor: u32,
+    /// the manifest of the database (as last saved)
+    manifest: Manifest,
+    /// the id of the replacement layer (if there is one)
+    replacement: Option<u64>,
 }
 impl SkdbDirAlloc {
-    /// Creates a new SkDB
+    /// Creates a new SkDB; refuses to if there's already a database (a manifest or any layer files) in the directory
     pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
         let path = path.as_ref();
 
         fs::create_dir_all(path)?;
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name == MANIFEST || name.parse::<u64>().is_ok() {
+                return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("there's already a database at {path:?}")).into());
+            }
+        }
+        let manifest = Manifest::default();
+        manifest.save(path)?;
         Ok(Self {
             path: path.to_path_buf(),
-            layers: Vec::new(),
-            cursor: 0,
+            manifest,
+            replacement: None,
         })
     }
     
-    /// Loads a Skdb from a directory
+    /// Loads a Skdb from a directory; drops the top layer if it never got (fully) committed
+    ///
+    /// Directories from before databases had a manifest get one built from their numbered layer files (upgrading the layers to the current format)
     pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        // grab file paths
-        let mut file_paths = Vec::new();
-        for entry in fs::read_dir(&path)? {
-            let entry = entry?;
-            if entry.file_type()?.is_file() {
-                file_paths.push(entry.path());
+        let path = path.as_ref();
+        let manifest = match Manifest::load(path) {
+            Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::NotFound => numbered_layers(path)?,
+            x => x?,
+        };
+        let mut alloc = Self {
+            path: path.to_path_buf(),
+            manifest,
+            replacement: None,
+        };
+
+        // drop a torn top layer
+        if let Some(id) = alloc.manifest.layers.last().copied() {
+            let layer = alloc.layer_path(id);
+            let len = fs::metadata(&layer)?.len();
+            match Layer::load(open_layer(&layer)?) {
+                Ok(x) if x.disk_size() <= len => (),
+                Ok(_) | Err(Error::DBCorrupt(_)) => <Self as Allocator>::drop_top_layer(&mut alloc)?,
+                Err(e) => return Err(e), // a layer of another format isn't torn
             }
         }
 
-        // index files and sort them
-        let mut layers = file_paths.iter()
-            .enumerate()
-            .filter_map(|(i, file)| file.file_name().map(|x| (x.to_string_lossy(), i)))
-            .filter_map(|(name, i)| name.parse::<u32>().ok().map(|x| (x, i)))
-            .collect::<Vec<(u32, usize)>>();
-        layers.sort_unstable_by_key(|x| x.0);
+        Ok(alloc)
+    }
 
-        let cursor = layers.last().map(|x| x.0 + 1).unwrap_or(0);
-        let layers = layers.into_iter()
-            .map(|(_, i)| std::mem::take(&mut file_paths[i]))
-            .collect::<Vec<_>>();
+    /// Grabs the manifest of the database
+    #[inline]
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
 
-        // return self
-        Ok(Self {
-            path: path.as_ref().to_path_buf(),
-            layers,
-            cursor,
-        })
+    /// The path of the layer file with the id
+    #[inline]
+    pub fn layer_path(&self, id: u64) -> PathBuf {
+        self.path.join(id.to_string())
+    }
+
+    /// Tags the layer (at the position in the database) with a name; the tag moves to whatever replaces the layer and goes once the layer is dropped
+    pub fn tag(&mut self, layer: usize, name: impl Into<String>) -> Result<(), Error> {
+        let id = *self.manifest.layers.get(layer).ok_or(Error::OutOfBounds)?;
+        self.update(|x| { x.tags.insert(name.into(), id); })
     }
+
+    /// Removes a tag
+    #[inline]
+    pub fn untag(&mut self, name: &str) -> Result<(), Error> {
+        self.update(|x| { x.tags.remove(name); })
+    }
+
+    /// Finds the position of the layer with the tag in the database
+    #[inline]
+    pub fn tagged(&self, name: &str) -> Option<usize> {
+        let id = self.manifest.tags.get(name)?;
+        self.manifest.layers.iter().position(|x| x == id)
+    }
+
+    /// Sets a database-level setting
+    #[inline]
+    pub fn set_setting(&mut self, key: impl Into<String>, value: impl Into<String>) -> Result<(), Error> {
+        self.update(|x| { x.settings.insert(key.into(), value.into()); })
+    }
+
+    /// Grabs a database-level setting
+    #[inline]
+    pub fn setting(&self, key: &str) -> Option<&str> {
+        self.manifest.settings.get(key).map(|x| x.as_str())
+    }
+
+    /// Changes the manifest; only keeping the change once it's been saved
+    fn update(&mut self, change: impl FnOnce(&mut Manifest)) -> Result<(), Error> {
+        let mut manifest = self.manifest.clone();
+        change(&mut manifest);
+        manifest.save(&self.path)?;
+        self.manifest = manifest;
+        Ok(())
+    }
+
+    /// Creates the file of a new top layer
+    fn create_layer(&mut self) -> Result<File, Error> {
+        let (id, file) = self.new_layer_file()?;
+        self.update(|x| x.layers.push(id))?;
+        Ok(file)
+    }
+
+    /// Creates the file of the replacement layer (removing the last one if it never replaced anything)
+    fn create_replacement(&mut self) -> Result<File, Error> {
+        if let Some(id) = self.replacement.take() { fs::remove_file(self.layer_path(id))? };
+        let (id, file) = self.new_layer_file()?;
+        self.replacement = Some(id);
+        Ok(file)
+    }
+
+    /// Creates the file of a layer with a new id; ids whose files already exist (leftovers of an interrupted change or stray files) get skipped
+    fn new_layer_file(&mut self) -> Result<(u64, File), Error> {
+        loop {
+            let id = self.manifest.new_id();
+            match create_layer(&self.layer_path(id)) {
+                Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                x => return Ok((id, x?)),
+            }
+        }
+    }
+
+    /// Removes the files of layers that are no longer in the manifest
+    #[inline]
+    fn remove_layers(&self, ids: impl IntoIterator<Item = u64>) -> Result<(), Error> {
+        for id in ids {
+            fs::remove_file(self.layer_path(id))?;
+        } Ok(())
+    }
+}
+
+/// Creates the file of a new layer; never overwrites an existing file
+#[inline]
+fn create_layer(path: &Path) -> Result<File, Error> {
+    Ok(File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(path)?)
+}
+
+/// Builds (and saves) the manifest of a directory from before databases had one, out of its layer files numbered in stack order;
+/// legacy layers get upgraded to the current format, with empty & torn top ones dropped
+fn numbered_layers(path: &Path) -> Result<Manifest, Error> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(path)? {
+        if let Ok(id) = entry?.file_name().to_string_lossy().parse::<u64>() { ids.push(id) };
+    }
+    ids.sort_unstable();
+
+    let mut manifest = Manifest { next_id: ids.last().map_or(0, |x| x + 1), ..Manifest::default() };
+    for (i, id) in ids.iter().copied().enumerate() {
+        let layer = path.join(id.to_string());
+        let loaded = match Layer::load(open_layer(&layer)?) {
+            Err(Error::InvalidLayer) => upgrade_layer(&layer),
+            x => x.map(|_| true),
+        };
+        let kept = match loaded {
+            Err(Error::DBCorrupt(_)) if i + 1 == ids.len() => { fs::remove_file(&layer)?; false },
+            x => x?,
+        };
+        if kept { manifest.layers.push(id) };
+    }
+
+    manifest.save(path)?;
+    Ok(manifest)
 }
+
+/// Rewrites a legacy layer file in the current format; `false` (with the file removed) if the layer was empty
+fn upgrade_layer(path: &Path) -> Result<bool, Error> {
+    let temp = path.with_extension("upgrade");
+    let stream = File::options().read(true).write(true).create(true).truncate(true).open(&temp)?;
+    let upgraded = Layer::load_legacy(std::io::BufReader::new(File::open(path)?), stream).and_then(|mut x| x.flush());
+    if let Err(e) = upgraded {
+        fs::remove_file(temp)?;
+        return Err(e);
+    }
+
+    let file = File::open(&temp)?;
+    if file.metadata()?.len() == 0 {
+        fs::remove_file(temp)?;
+        fs::remove_file(path)?;
+        return Ok(false);
+    }
+    file.sync_all()?;
+    fs::rename(temp, path)?;
+    Ok(true)
+}
+
+/// Opens an existing layer file for reading & writing
+#[inline]
+fn open_layer(path: &Path) -> Result<File, Error> {
+    Ok(File::options()
+        .read(true)
+        .write(true)
+        .append(false)
+        .truncate(false)
+        .open(path)?)
+}
+
 impl<'a> Allocator<'a> for SkdbDirAlloc {
     type LayerStream = File;
 
     /// Loads the layer files from the directory
     fn load_layers(&self) -> Result<Vec<Layer<'a, Self::LayerStream>>, Error> {
-        let mut layers = Vec::with_capacity(self.layers.len());
-        for path in self.layers.iter() {
-            let file = File::options()
-                .read(true)
-                .write(true)
-                .append(false)
-                .truncate(false)
-                .open(path)?;
-            layers.push(Layer::load(file)?);
-        } Ok(layers)
+        self.manifest.layers.iter().map(|id| Layer::load(open_layer(&self.layer_path(*id))?)).collect()
+    }
+
+    fn add_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        Ok(Layer::new(self.create_layer()?))
+    }
+
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        let id = if let Some(x) = self.manifest.layers.last().copied() { x } else { return Ok(()) };
+        self.update(|x| {
+            x.layers.pop();
+            x.untag_layer(id);
+        })?;
+        self.remove_layers([id])
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        let id = if let Some(x) = self.manifest.layers.get(layer).copied() { x } else { return Ok(()) };
+        self.update(|x| {
+            x.layers.remove(layer);
+            x.untag_layer(id);
+        })?;
+        self.remove_layers([id])
+    }
+
+    /// Drops the layers in a single update of the manifest
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        let ids = layers.iter().filter_map(|x| self.manifest.layers.get(*x).copied()).collect::<Vec<_>>();
+        self.update(|x| {
+            x.layers.retain(|id| !ids.contains(id));
+            for id in ids.iter() { x.untag_layer(*id) };
+        })?;
+        self.remove_layers(ids)
+    }
+
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        let old = self.manifest.layers[..std::cmp::min(top_layer, self.manifest.layers.len())].to_vec();
+        self.update(|x| {
+            x.layers.drain(..old.len());
+            for id in old.iter() { x.untag_layer(*id) };
+        })?;
+        self.remove_layers(old)
+    }
+
+    fn add_replacement_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        Ok(Layer::new(self.create_replacement()?))
+    }
+
+    /// Swaps the replacement in for the layers in the manifest; its tags move to the replacement
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        if layers.is_empty() || layers.end > self.manifest.layers.len() { return Err(Error::OutOfBounds) }; // nothing to replace
+        let id = self.replacement.ok_or(Error::OutOfBounds)?;
+        let old = self.manifest.layers[layers.clone()].to_vec();
+        self.update(|x| {
+            x.layers.splice(layers, [id]);
+            for tag in x.tags.values_mut().filter(|x| old.contains(x)) { *tag = id };
+        })?;
+        self.replacement = None;
+        self.remove_layers(old)
+    }
+}
+
+/// # Memory-Mapped Stream
+/// ---
+/// A layer file that gets memory-mapped once written, so reads are served straight from the mapping (without syscalls or copies)
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MmapStream {
+    /// the underlying layer file
+    file: File,
+    /// the mapping of the file (if there is anything written to it)
+    map: Option<memmap2::Mmap>,
+    /// the position of the stream
+    pos: u64,
+}
+#[cfg(feature = "mmap")]
+impl MmapStream {
+    /// Wraps the layer file and maps whatever is already written to it
+    pub fn new(file: File) -> Result<Self, Error> {
+        let mut stream = Self { file, map: None, pos: 0 };
+        stream.remap()?;
+        Ok(stream)
+    }
+
+    /// Re-maps the file after it's been written to
+    fn remap(&mut self) -> Result<(), Error> {
+        self.map = None;
+        if self.file.metadata()?.len() == 0 { return Ok(()) }; // empty files can't be mapped
+
+        // safety: committed layer files are never written to again & the database owns them
+        self.map = Some(unsafe { memmap2::Mmap::map(&self.file)? });
+        Ok(())
+    }
+}
+#[cfg(feature = "mmap")]
+impl Read for MmapStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(map) = self.map.as_ref() {
+            let data = map.get(self.pos as usize..).unwrap_or(&[]);
+            let len = std::cmp::min(data.len(), buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            self.pos += len as u64;
+            return Ok(len);
+        }
+
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let len = self.file.read(buf)?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+#[cfg(feature = "mmap")]
+impl Write for MmapStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.map = None; // the mapping is stale until flushed
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let len = self.file.write(buf)?;
+        self.pos += len as u64;
+        Ok(len)
     }
 
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if self.map.is_none() {
+            self.remap().map_err(|e| match e {
+                Error::IOError(e) => e,
+                e => std::io::Error::other(format!("{e:?}")),
+            })?;
+        } Ok(())
+    }
+}
+#[cfg(feature = "mmap")]
+impl Seek for MmapStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(x) => x,
+            SeekFrom::End(x) => self.file.seek(SeekFrom::End(x))?,
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x).ok_or(std::io::ErrorKind::InvalidInput)?,
+        }; Ok(self.pos)
+    }
+}
+#[cfg(feature = "mmap")]
+impl LayerStream for MmapStream {
+    #[inline]
+    fn as_slice(&self) -> Option<&[u8]> {
+        self.map.as_deref()
+    }
+}
+
+/// # Memory-Mapped Directory Allocator
+/// ---
+/// A `SkdbDirAlloc` (same directory format) whose committed layers are memory-mapped for read-heavy workloads
+///
+/// **note:** only available with the (opt-in) `mmap` feature
+#[cfg(feature = "mmap")]
+pub struct SkdbMmapAlloc(pub SkdbDirAlloc);
+#[cfg(feature = "mmap")]
+impl SkdbMmapAlloc {
+    /// Creates a new memory-mapped SkDB
+    #[inline]
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        SkdbDirAlloc::new(path).map(Self)
+    }
+
+    /// Loads a memory-mapped Skdb from a directory
+    #[inline]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        SkdbDirAlloc::load(path).map(Self)
+    }
+}
+#[cfg(feature = "mmap")]
+impl<'a> Allocator<'a> for SkdbMmapAlloc {
+    type LayerStream = MmapStream;
+
+    /// Loads & maps the layer files from the directory
+    fn load_layers(&self) -> Result<Vec<Layer<'a, Self::LayerStream>>, Error> {
+        self.0.manifest.layers.iter().map(|id| Layer::load(MmapStream::new(open_layer(&self.0.layer_path(*id))?)?)).collect()
+    }
+    #[inline]
     fn add_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
-        let path = self.path.join(self.cursor.to_string());
+        Ok(Layer::new(MmapStream::new(self.0.create_layer()?)?))
+    }
+    #[inline]
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        <SkdbDirAlloc as Allocator<'a>>::drop_top_layer(&mut self.0)
+    }
+    #[inline]
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        <SkdbDirAlloc as Allocator<'a>>::drop_layer(&mut self.0, layer)
+    }
+    #[inline]
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        <SkdbDirAlloc as Allocator<'a>>::drop_layers(&mut self.0, layers)
+    }
+    #[inline]
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        <SkdbDirAlloc as Allocator<'a>>::rebase(&mut self.0, top_layer)
+    }
+    #[inline]
+    fn add_replacement_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        Ok(Layer::new(MmapStream::new(self.0.create_replacement()?)?))
+    }
+    #[inline]
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        <SkdbDirAlloc as Allocator<'a>>::replace_layers(&mut self.0, layers)
+    }
+}
+
+/// Where the layer of a single-file database currently lives
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// At the position in the database file
+    File(u64),
+    /// At the start of the replacement file
+    Replacement,
+}
+
+/// The files of a single-file database, shared by all of its layer streams
+#[derive(Debug)]
+struct FileState {
+    /// the database file
+    file: File,
+    /// the file of the replacement layer (if there is one)
+    replacement: Option<File>,
+    /// where each layer (by id) lives
+    locations: HashMap<u64, Location>,
+}
+
+/// # Single-File Layer Stream
+/// ---
+/// The region of a layer within a single-file database; follows the layer around when the file gets rewritten
+#[derive(Debug)]
+pub struct FileRegion {
+    /// the shared files of the database
+    state: Arc<Mutex<FileState>>,
+    /// the id of the layer
+    id: u64,
+    /// the position of the stream within the layer
+    pos: u64,
+}
+impl FileRegion {
+    /// Runs an operation on the file the layer lives in, positioned at the stream position
+    fn with_file<T>(&mut self, op: impl FnOnce(&mut File) -> std::io::Result<T>) -> std::io::Result<T> {
+        let mut state = self.state.lock().map_err(|_| std::io::Error::other("poisoned database file lock"))?;
+        let (file, start) = match state.locations.get(&self.id).copied() {
+            Some(Location::File(start)) => (&mut state.file, start),
+            Some(Location::Replacement) => (state.replacement.as_mut().ok_or(std::io::ErrorKind::NotFound)?, 0),
+            None => return Err(std::io::ErrorKind::NotFound.into()), // the layer got dropped
+        };
+
+        file.seek(SeekFrom::Start(start + self.pos))?;
+        op(file)
+    }
+}
+impl Read for FileRegion {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.with_file(|file| file.read(buf))?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+impl Write for FileRegion {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.with_file(|file| file.write(buf))?;
+        self.pos += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.with_file(|file| file.flush())
+    }
+}
+impl Seek for FileRegion {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(x) => x,
+            SeekFrom::End(_) => return Err(std::io::ErrorKind::Unsupported.into()), // regions don't know where they end
+            SeekFrom::Current(x) => self.pos.checked_add_signed(x).ok_or(std::io::ErrorKind::InvalidInput)?,
+        }; Ok(self.pos)
+    }
+}
+impl LayerStream for FileRegion {}
+
+/// # Single-File Allocator
+/// ---
+/// Allocates all the layers within a single append-only file, one after the other;
+/// the layers are found by following the lengths in their headers, and anything that isn't appended to the top gets written to a new file that atomically replaces the old one
+///
+/// **note:** dropping any layer but the top one rewrites the whole file, so streaming rebases (`StackDB::rebase_streaming`) aren't supported
+#[derive(Debug)]
+pub struct SkdbFileAlloc {
+    /// the path of the database file
+    pub path: PathBuf,
+    /// the ids of the layers in the database (bottom layer first)
+    layers: Vec<u64>,
+    /// the id of the next layer
+    next_id: u64,
+    /// the files of the database (shared with the layer streams)
+    state: Arc<Mutex<FileState>>,
+}
+impl SkdbFileAlloc {
+    /// Creates a new single-file SkDB
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
         let file = File::options()
             .read(true)
             .write(true)
-            .append(false)
-            .truncate(false)
             .create_new(true)
-            .open(&path)?;
-        self.cursor += 1;
-        self.layers.push(path);
-        Ok(Layer::new(file))
+            .open(path.as_ref())?;
+        Ok(Self::from_file(path.as_ref().to_path_buf(), file))
     }
 
-    fn drop_top_layer(&mut self) -> Result<(), Error> {
-        let path = if let Some(x) = self.layers.pop() { x } else { return Ok(()) };
-        self.cursor -= 1;
-        fs::remove_file(path)?;
+    /// Loads a single-file SkDB; a partially written top layer (from a commit that never finished) gets truncated off
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+        let file_len = file.metadata()?.len();
+        let mut alloc = Self::from_file(path.as_ref().to_path_buf(), file);
+
+        // follow the layer headers through the file
+        let mut start = 0;
+        while start < file_len {
+            alloc.place(alloc.next_id, Location::File(start))?;
+            let size = match Layer::load(alloc.region(alloc.next_id)) {
+                Ok(layer) if start + layer.disk_size() <= file_len => layer.disk_size(),
+                Err(Error::InvalidLayer) => return Err(Error::InvalidLayer), // a layer of another format isn't torn
+                _ => { // torn layer
+                    alloc.state()?.file.set_len(start)?;
+                    break;
+                },
+            };
+
+            alloc.layers.push(alloc.next_id);
+            alloc.next_id += 1;
+            start += size;
+        }
+
+        alloc.state()?.locations.retain(|_, x| matches!(x, Location::File(x) if *x < start));
+        Ok(alloc)
+    }
+
+    /// Wraps the database file (without any layers yet)
+    #[inline]
+    fn from_file(path: PathBuf, file: File) -> Self {
+        Self {
+            path,
+            layers: Vec::new(),
+            next_id: 0,
+            state: Arc::new(Mutex::new(FileState {
+                file,
+                replacement: None,
+                locations: HashMap::new(),
+            })),
+        }
+    }
 
+    /// Locks the shared files of the database
+    #[inline]
+    fn state(&self) -> Result<std::sync::MutexGuard<'_, FileState>, Error> {
+        self.state.lock().map_err(|_| Error::Custom("poisoned database file lock".into()))
+    }
+
+    /// Sets where a layer lives
+    #[inline]
+    fn place(&self, id: u64, location: Location) -> Result<(), Error> {
+        self.state()?.locations.insert(id, location);
         Ok(())
     }
 
+    /// Creates a stream over the region of a layer
+    #[inline]
+    fn region(&self, id: u64) -> FileRegion {
+        FileRegion { state: self.state.clone(), id, pos: 0 }
+    }
+
+    /// The path of the file the replacement layer (or a rewritten database) gets written to
+    #[inline]
+    fn temp_path(&self, extension: &str) -> PathBuf {
+        self.path.with_extension(extension)
+    }
+
+    /// Writes a new database file without the layers (sorted positions), with the replacement layer (if there is one) in place of the first of them, and atomically swaps it in
+    fn rewrite(&mut self, layers: &[usize], replacement: bool) -> Result<(), Error> {
+        let path = self.temp_path(REWRITE_EXTENSION);
+        let mut new = File::options().read(true).write(true).truncate(true).create(true).open(&path)?;
+
+        let mut state = self.state()?;
+        let file_len = state.file.metadata()?.len();
+        let mut starts = Vec::with_capacity(self.layers.len());
+        let mut order = Vec::with_capacity(self.layers.len());
+        let mut removed = Vec::with_capacity(layers.len());
+        for (i, id) in self.layers.iter().enumerate() {
+            if layers.binary_search(&i).is_err() { order.push((*id, Some(*id))); continue };
+            if replacement && layers.first() == Some(&i) { order.push((self.next_id, None)) };
+            removed.push(*id);
+        }
+
+        // copy the layers over in order
+        for (id, old) in order.iter() {
+            let start = new.stream_position()?;
+            match old.and_then(|x| state.locations.get(&x).copied()) {
+                Some(Location::File(from)) => {
+                    let end = self.layers.iter()
+                        .filter_map(|x| match state.locations.get(x) { Some(Location::File(x)) if *x > from => Some(*x), _ => None })
+                        .min()
+                        .unwrap_or(file_len);
+                    state.file.seek(SeekFrom::Start(from))?;
+                    std::io::copy(&mut (&mut state.file).take(end - from), &mut new)?;
+                },
+                _ => if let Some(file) = state.replacement.as_mut() {
+                    file.rewind()?;
+                    std::io::copy(file, &mut new)?;
+                },
+            } starts.push((*id, start));
+        }
+
+        // swap in the new file (once it's durable)
+        new.sync_all()?;
+        fs::rename(&path, &self.path)?;
+        state.file = new;
+        for id in removed {
+            state.locations.remove(&id);
+        }
+        for (id, start) in starts.iter() {
+            state.locations.insert(*id, Location::File(*start));
+        }
+
+        // the replacement layer now lives in the database file
+        if replacement {
+            state.replacement = None;
+            drop(state);
+            let _ = fs::remove_file(self.temp_path(REPLACEMENT_EXTENSION));
+            self.next_id += 1;
+        } else { drop(state) };
+        self.layers = order.into_iter().map(|(id, _)| id).collect();
+        Ok(())
+    }
+}
+impl<'a> Allocator<'a> for SkdbFileAlloc {
+    type LayerStream = FileRegion;
+
+    fn load_layers(&self) -> Result<Vec<Layer<'a, Self::LayerStream>>, Error> {
+        self.layers.iter().map(|id| Layer::load(self.region(*id))).collect()
+    }
+
+    /// Appends a layer to the end of the file
+    fn add_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        let id = self.next_id;
+        let start = self.state()?.file.metadata()?.len();
+        self.place(id, Location::File(start))?;
+        self.next_id += 1;
+        self.layers.push(id);
+        Ok(Layer::new(self.region(id)))
+    }
+
+    /// Truncates the top layer off the end of the file
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        let id = if let Some(x) = self.layers.pop() { x } else { return Ok(()) };
+        let mut state = self.state()?;
+        if let Some(Location::File(start)) = state.locations.remove(&id) {
+            state.file.set_len(start)?;
+        } Ok(())
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        if layer >= self.layers.len() { return Ok(()) };
+        if layer == self.layers.len()-1 { return <Self as Allocator<'a>>::drop_top_layer(self) };
+        self.rewrite(&[layer], false)
+    }
+
+    /// Dropped layers only free their space once the rest of the file gets rewritten
+    #[inline]
+    fn releases_in_place(&self) -> bool {
+        false
+    }
+
+    /// Drops all the layers with a single rewrite of the file (instead of one per layer)
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        let mut layers = layers.iter().copied().filter(|x| *x < self.layers.len()).collect::<Vec<_>>();
+        layers.sort_unstable();
+        layers.dedup();
+        match layers.as_slice() {
+            [] => Ok(()),
+            [x] => <Self as Allocator<'a>>::drop_layer(self, *x),
+            _ => self.rewrite(&layers, false),
+        }
+    }
+
     fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
-        let mut top = Vec::with_capacity(self.layers.len()-top_layer);
-        top.extend(self.layers.drain(top_layer..));
+        self.rewrite(&(0..top_layer).collect::<Vec<_>>(), false)
+    }
+
+    fn add_replacement_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(self.temp_path(REPLACEMENT_EXTENSION))?;
+
+        let mut state = self.state()?;
+        state.replacement = Some(file);
+        state.locations.insert(self.next_id, Location::Replacement);
+        drop(state);
+        Ok(Layer::new(self.region(self.next_id)))
+    }
+
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        if layers.is_empty() || layers.end > self.layers.len() || self.state()?.replacement.is_none() { return Err(Error::OutOfBounds) };
+        self.rewrite(&layers.collect::<Vec<_>>(), true)
+    }
+}
+
+/// The extension of the file a single-file database gets rewritten into before it's swapped in
+const REWRITE_EXTENSION: &str = "rewrite";
+/// The extension of the file the replacement layer of a single-file database gets written to
+const REPLACEMENT_EXTENSION: &str = "replacement";
+
+/// The tier a layer of a tiered database lives in
+#[derive(Debug)]
+enum Tier {
+    /// In memory (recent layers)
+    Memory(MemStream),
+    /// In a layer file on disk (older layers)
+    Disk(File),
+}
+
+/// # Tiered Layer Stream
+/// ---
+/// A layer of a tiered database; follows the layer when it gets spilled from memory to disk
+#[derive(Debug, Clone)]
+pub struct TieredStream(Arc<Mutex<Tier>>);
+impl TieredStream {
+    /// Runs an operation on the stream of whatever tier the layer lives in
+    #[inline]
+    fn with_tier<T>(&self, op: impl FnOnce(&mut dyn LayerIo) -> std::io::Result<T>) -> std::io::Result<T> {
+        let mut tier = self.0.lock().map_err(|_| std::io::Error::other("poisoned tiered layer lock"))?;
+        match &mut *tier {
+            Tier::Memory(x) => op(x),
+            Tier::Disk(x) => op(x),
+        }
+    }
+}
+/// The i/o operations a tier's stream supports
+trait LayerIo: Read + Write + Seek {}
+impl<T: Read + Write + Seek> LayerIo for T {}
+impl Read for TieredStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.with_tier(|x| x.read(buf))
+    }
+}
+impl Write for TieredStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.with_tier(|x| x.write(buf))
+    }
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.with_tier(|x| x.flush())
+    }
+}
+impl Seek for TieredStream {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.with_tier(|x| x.seek(pos))
+    }
+}
+impl LayerStream for TieredStream {}
+
+/// # Tiered Allocator
+/// ---
+/// Keeps the most recent layers in memory for fast reads and spills the older ones to a `SkdbDirAlloc` directory once the memory tier outgrows its budget
+/// (checked on every commit & whenever a layer gets added)
+///
+/// **note:** layers still in memory are lost on exit unless they're spilled first (with `spill(0)`)
+pub struct SkdbTieredAlloc {
+    /// the disk tier (always the bottom layers)
+    pub disk: SkdbDirAlloc,
+    /// the layers of the database (bottom layer first); the ones above the disk tier live in memory
+    layers: Vec<TieredStream>,
+    /// the stream of the replacement layer (if there is one)
+    replacement: Option<TieredStream>,
+    /// the maximum bytes of committed layers kept in memory
+    pub budget: usize,
+}
+impl SkdbTieredAlloc {
+    /// Creates a new tiered SkDB with the disk tier in a directory
+    #[inline]
+    pub fn new(path: impl AsRef<Path>, budget: usize) -> Result<Self, Error> {
+        Self::with_disk(SkdbDirAlloc::new(path)?, budget)
+    }
+
+    /// Loads a tiered SkDB from the directory of its disk tier
+    #[inline]
+    pub fn load(path: impl AsRef<Path>, budget: usize) -> Result<Self, Error> {
+        Self::with_disk(SkdbDirAlloc::load(path)?, budget)
+    }
+
+    /// Puts a memory tier on top of the disk tier
+    fn with_disk(disk: SkdbDirAlloc, budget: usize) -> Result<Self, Error> {
+        let layers = disk.manifest.layers.iter()
+            .map(|id| Ok(TieredStream(Arc::new(Mutex::new(Tier::Disk(open_layer(&disk.layer_path(*id))?))))))
+            .collect::<Result<_, Error>>()?;
+        Ok(Self { disk, layers, replacement: None, budget })
+    }
 
-        // delete the other layer files
-        for path in self.layers.iter() {
-            fs::remove_file(path)?;
+    /// Creates a new in-memory layer stream
+    #[inline]
+    fn memory_stream() -> Result<TieredStream, Error> {
+        Ok(TieredStream(Arc::new(Mutex::new(Tier::Memory(MemStream::new(MemSlot::default())?)))))
+    }
+
+    /// Grabs the committed bytes of the layer if it lives in memory
+    #[inline]
+    fn committed(stream: &TieredStream) -> Result<Option<Arc<[u8]>>, Error> {
+        match &*stream.0.lock().map_err(|_| Error::Custom("poisoned tiered layer lock".into()))? {
+            Tier::Memory(MemStream { buffer: MemBuffer::Committed(x), .. }) if !x.is_empty() => Ok(Some(x.clone())),
+            _ => Ok(None),
         }
+    }
+
+    /// The bytes of committed layers in the memory tier
+    pub fn memory_size(&self) -> Result<usize, Error> {
+        let mut size = 0;
+        for stream in self.layers[self.disk.manifest.layers.len()..].iter() {
+            size += Self::committed(stream)?.map(|x| x.len()).unwrap_or(0);
+        } Ok(size)
+    }
+
+    /// Moves the oldest committed layers in memory to disk until the memory tier fits within the budget
+    pub fn spill(&mut self, budget: usize) -> Result<(), Error> {
+        while self.memory_size()? > budget {
+            // the lowest memory layer goes on top of the disk tier
+            let stream = self.layers[self.disk.manifest.layers.len()].clone();
+            let data = if let Some(x) = Self::committed(&stream)? { x } else { break }; // only the uncommitted layer is left
+
+            let mut file = self.disk.create_layer()?;
+            file.write_all(&data)?;
+            file.flush()?;
+
+            let mut tier = stream.0.lock().map_err(|_| Error::Custom("poisoned tiered layer lock".into()))?;
+            if let Tier::Memory(x) = &*tier { file.seek(SeekFrom::Start(x.pos))?; };
+            *tier = Tier::Disk(file);
+        } Ok(())
+    }
+}
+impl<'a> Allocator<'a> for SkdbTieredAlloc {
+    type LayerStream = TieredStream;
+
+    fn load_layers(&self) -> Result<Vec<Layer<'a, Self::LayerStream>>, Error> {
+        self.layers.iter().map(|x| Layer::load(x.clone())).collect()
+    }
+
+    /// Adds an in-memory layer (spilling the older ones if the memory tier is over budget)
+    fn add_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        self.spill(self.budget)?;
+        let stream = Self::memory_stream()?;
+        self.layers.push(stream.clone());
+        Ok(Layer::new(stream))
+    }
+
+    /// Spills the older layers if the newly committed one put the memory tier over budget
+    #[inline]
+    fn layer_committed(&mut self) -> Result<(), Error> {
+        self.spill(self.budget)
+    }
+
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        if self.layers.len() == self.disk.manifest.layers.len() { <SkdbDirAlloc as Allocator<'a>>::drop_top_layer(&mut self.disk)? };
+        self.layers.pop();
+        Ok(())
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        if layer >= self.layers.len() { return Ok(()) };
+        if layer < self.disk.manifest.layers.len() { <SkdbDirAlloc as Allocator<'a>>::drop_layer(&mut self.disk, layer)? };
+        self.layers.remove(layer);
+        Ok(())
+    }
+
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        for _ in 0..std::cmp::min(top_layer, self.layers.len()) {
+            <Self as Allocator<'a>>::drop_layer(self, 0)?;
+        } Ok(())
+    }
+
+    /// Adds the replacement layer in memory (it gets moved to disk if it replaces any disk layers)
+    fn add_replacement_layer(&mut self) -> Result<Layer<'a, Self::LayerStream>, Error> {
+        let stream = Self::memory_stream()?;
+        self.replacement = Some(stream.clone());
+        Ok(Layer::new(stream))
+    }
+
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        if layers.is_empty() || layers.end > self.layers.len() { return Err(Error::OutOfBounds) };
+        let stream = self.replacement.take().ok_or(Error::OutOfBounds)?;
+
+        // keep the disk tier below the memory tier
+        let disk = layers.start..std::cmp::min(layers.end, self.disk.manifest.layers.len());
+        if !disk.is_empty() {
+            let data = Self::committed(&stream)?.unwrap_or_else(|| Arc::from([]));
+            let mut file = self.disk.create_replacement()?;
+            file.write_all(&data)?;
+            file.flush()?;
+            for _ in disk.end..layers.end { self.layers.remove(disk.end); } // memory layers in the run
+            <SkdbDirAlloc as Allocator<'a>>::replace_layers(&mut self.disk, disk.clone())?;
 
-        // move the base layer
-        self.layers.clear();
-        for (i, path) in top.into_iter().enumerate() {
-            let new_path = self.path.join(i.to_string());
-            fs::rename(path, &new_path)?;
-            self.layers.push(new_path);
+            let mut file = open_layer(&self.disk.layer_path(self.disk.manifest.layers[disk.start]))?;
+            let mut tier = stream.0.lock().map_err(|_| Error::Custom("poisoned tiered layer lock".into()))?;
+            if let Tier::Memory(x) = &*tier { file.seek(SeekFrom::Start(x.pos))?; };
+            *tier = Tier::Disk(file);
+            drop(tier);
+            self.layers.splice(disk, [stream]);
+        } else {
+            self.layers.splice(layers, [stream]);
         } Ok(())
     }
 }
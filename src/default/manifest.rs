@@ -0,0 +1,114 @@
+//! The manifest of a directory database; the record of its layers, tags & settings that gets swapped out atomically on every change
+
+use std::{collections::BTreeMap, fs::{self, File}, io::Write, path::Path};
+use crate::errors::Error;
+
+/// The file name of the manifest in a directory database
+pub const MANIFEST: &str = "MANIFEST";
+/// The file name of the manifest while it's being written (before it atomically replaces the old one)
+const MANIFEST_TEMP: &str = "MANIFEST.tmp";
+/// The first bytes of a manifest (and its format version)
+const MAGIC: &[u8; 8] = b"SKDBMAN1";
+
+/// The ordered layers, tags & database-level settings of a directory database
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// the unique ids of the layers in the database (bottom layer first)
+    pub layers: Vec<u64>,
+    /// the named tags of layers (by their ids)
+    pub tags: BTreeMap<String, u64>,
+    /// the database-level settings
+    pub settings: BTreeMap<String, String>,
+    /// the id the next layer gets (ids never get reused)
+    pub next_id: u64,
+}
+
+impl Manifest {
+    /// Reads the manifest of a directory database
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let bytes = fs::read(dir.as_ref().join(MANIFEST))?;
+        Self::from_bytes(&bytes).ok_or(Error::InvalidManifest)
+    }
+
+    /// Atomically replaces the manifest of a directory database with this one
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<(), Error> {
+        let temp = dir.as_ref().join(MANIFEST_TEMP);
+        let mut file = File::create(&temp)?;
+        file.write_all(&self.to_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp, dir.as_ref().join(MANIFEST))?;
+        Ok(())
+    }
+
+    /// Hands out a new unique layer id
+    #[inline]
+    pub fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Removes the tags of the layer
+    #[inline]
+    pub fn untag_layer(&mut self, id: u64) {
+        self.tags.retain(|_, x| *x != id);
+    }
+
+    /// Encodes the manifest
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&self.next_id.to_be_bytes());
+        bytes.extend_from_slice(&(self.layers.len() as u64).to_be_bytes());
+        for id in self.layers.iter() { bytes.extend_from_slice(&id.to_be_bytes()) };
+        bytes.extend_from_slice(&(self.tags.len() as u64).to_be_bytes());
+        for (name, id) in self.tags.iter() {
+            put_str(&mut bytes, name);
+            bytes.extend_from_slice(&id.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(self.settings.len() as u64).to_be_bytes());
+        for (key, value) in self.settings.iter() {
+            put_str(&mut bytes, key);
+            put_str(&mut bytes, value);
+        } bytes
+    }
+
+    /// Decodes a manifest; `None` if it's invalid
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut bytes = bytes.strip_prefix(MAGIC)?;
+        let next_id = take_u64(&mut bytes)?;
+
+        let mut layers = Vec::new();
+        for _ in 0..take_u64(&mut bytes)? { layers.push(take_u64(&mut bytes)?) };
+        let mut tags = BTreeMap::new();
+        for _ in 0..take_u64(&mut bytes)? { tags.insert(take_str(&mut bytes)?, take_u64(&mut bytes)?); };
+        let mut settings = BTreeMap::new();
+        for _ in 0..take_u64(&mut bytes)? { settings.insert(take_str(&mut bytes)?, take_str(&mut bytes)?); };
+
+        if !bytes.is_empty() || layers.iter().any(|x| *x >= next_id) { return None };
+        Some(Self { layers, tags, settings, next_id })
+    }
+}
+
+/// Appends a length-prefixed string
+#[inline]
+fn put_str(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u64).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Takes a u64 off the front of the bytes
+#[inline]
+fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+    let (x, rest) = bytes.split_first_chunk::<8>()?;
+    *bytes = rest;
+    Some(u64::from_be_bytes(*x))
+}
+
+/// Takes a length-prefixed string off the front of the bytes
+#[inline]
+fn take_str(bytes: &mut &[u8]) -> Option<String> {
+    let len = usize::try_from(take_u64(bytes)?).ok()?;
+    if len > bytes.len() { return None };
+    let (x, rest) = bytes.split_at(len);
+    *bytes = rest;
+    String::from_utf8(x.to_vec()).ok()
+}
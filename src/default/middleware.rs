@@ -0,0 +1,562 @@
+//! Composable wrappers around any `Allocator` (and its layer streams) that count, log or fail its operations
+//!
+//! **note:** the wrapped layer streams never lend their bytes (see `LayerStream::as_slice`) so every read goes through the wrapper
+
+use std::{io::{Read, Seek, SeekFrom, Write}, ops::Range, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+use crate::{base::{database::allocator::Allocator, layer::{Layer, LayerStream}}, errors::Error};
+
+/// The operation counts & bytes moved by an allocator & its layer streams
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocMetrics {
+    /// The amount of times the layers got loaded
+    pub loads: u64,
+    /// The amount of layers added (including replacement layers)
+    pub layers_added: u64,
+    /// The amount of layers dropped (not counting rebases)
+    pub layers_dropped: u64,
+    /// The amount of rebases
+    pub rebases: u64,
+    /// The amount of runs of layers replaced
+    pub replacements: u64,
+    /// The amount of reads from the layer streams
+    pub reads: u64,
+    /// The bytes read from the layer streams
+    pub bytes_read: u64,
+    /// The amount of writes to the layer streams
+    pub writes: u64,
+    /// The bytes written to the layer streams
+    pub bytes_written: u64,
+    /// The amount of seeks in the layer streams
+    pub seeks: u64,
+    /// The amount of flushes of the layer streams
+    pub flushes: u64,
+}
+
+/// Grabs the lock of some state shared between a wrapper & its streams
+#[inline]
+fn lock<T>(state: &Mutex<T>) -> std::io::Result<std::sync::MutexGuard<'_, T>> {
+    state.lock().map_err(|_| std::io::Error::other("poisoned allocator middleware lock"))
+}
+
+/// # Metrics Allocator
+/// ---
+/// Counts the operations of an allocator & the bytes its layer streams move
+#[derive(Debug)]
+pub struct MetricsAlloc<A> {
+    /// The wrapped allocator
+    pub inner: A,
+    /// The metrics shared with the layer streams
+    metrics: Arc<Mutex<AllocMetrics>>,
+}
+
+impl<A> MetricsAlloc<A> {
+    /// Starts counting the operations of the allocator
+    #[inline]
+    pub fn new(inner: A) -> Self {
+        Self { inner, metrics: Arc::default() }
+    }
+
+    /// Grabs the metrics counted so far
+    #[inline]
+    pub fn metrics(&self) -> AllocMetrics {
+        lock(&self.metrics).map(|x| *x).unwrap_or_default()
+    }
+
+    /// Resets the metrics back to zero
+    #[inline]
+    pub fn reset(&self) {
+        if let Ok(mut x) = lock(&self.metrics) { *x = AllocMetrics::default() };
+    }
+
+    /// Counts an operation
+    #[inline]
+    fn count(&self, op: impl FnOnce(&mut AllocMetrics)) {
+        if let Ok(mut x) = lock(&self.metrics) { op(&mut x) };
+    }
+
+    /// Wraps a layer of the inner allocator
+    #[inline]
+    fn wrap<'l, S: LayerStream>(&self, layer: Layer<'l, S>) -> Layer<'l, MetricsStream<S>> {
+        layer.map_stream(|inner| MetricsStream { inner, metrics: self.metrics.clone() })
+    }
+}
+
+/// A layer stream that counts its operations & the bytes it moves
+#[derive(Debug)]
+pub struct MetricsStream<S> {
+    /// The wrapped layer stream
+    inner: S,
+    /// The metrics of the allocator
+    metrics: Arc<Mutex<AllocMetrics>>,
+}
+
+impl<S: LayerStream> Read for MetricsStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut metrics = lock(&self.metrics)?;
+        metrics.reads += 1;
+        metrics.bytes_read += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: LayerStream> Write for MetricsStream<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut metrics = lock(&self.metrics)?;
+        metrics.writes += 1;
+        metrics.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()?;
+        lock(&self.metrics)?.flushes += 1;
+        Ok(())
+    }
+}
+
+impl<S: LayerStream> Seek for MetricsStream<S> {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = self.inner.seek(pos)?;
+        lock(&self.metrics)?.seeks += 1;
+        Ok(pos)
+    }
+}
+
+impl<S: LayerStream> LayerStream for MetricsStream<S> {}
+
+impl<'l, A: Allocator<'l>> Allocator<'l> for MetricsAlloc<A> {
+    type LayerStream = MetricsStream<A::LayerStream>;
+
+    fn load_layers(&self) -> Result<Vec<Layer<'l, Self::LayerStream>>, Error> {
+        let layers = self.inner.load_layers()?;
+        self.count(|x| x.loads += 1);
+        Ok(layers.into_iter().map(|x| self.wrap(x)).collect())
+    }
+
+    fn add_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        let layer = self.inner.add_layer()?;
+        self.count(|x| x.layers_added += 1);
+        Ok(self.wrap(layer))
+    }
+
+    fn releases_in_place(&self) -> bool {
+        self.inner.releases_in_place()
+    }
+
+    fn layer_committed(&mut self) -> Result<(), Error> {
+        self.inner.layer_committed()
+    }
+
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        self.inner.drop_top_layer()?;
+        self.count(|x| x.layers_dropped += 1);
+        Ok(())
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        self.inner.drop_layer(layer)?;
+        self.count(|x| x.layers_dropped += 1);
+        Ok(())
+    }
+
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        self.inner.drop_layers(layers)?;
+        self.count(|x| x.layers_dropped += layers.len() as u64);
+        Ok(())
+    }
+
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        self.inner.rebase(top_layer)?;
+        self.count(|x| x.rebases += 1);
+        Ok(())
+    }
+
+    fn add_replacement_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        let layer = self.inner.add_replacement_layer()?;
+        self.count(|x| x.layers_added += 1);
+        Ok(self.wrap(layer))
+    }
+
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        self.inner.replace_layers(layers)?;
+        self.count(|x| x.replacements += 1);
+        Ok(())
+    }
+}
+
+/// Where the log lines of a `LoggedAlloc` go
+pub type Logger = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// # Logged Allocator
+/// ---
+/// Logs every call to an allocator & its layer streams (and what they returned)
+pub struct LoggedAlloc<A> {
+    /// The wrapped allocator
+    pub inner: A,
+    /// Where the log lines go
+    log: Logger,
+    /// The id of the next layer stream handed out (so the log lines of different streams can be told apart)
+    next_stream: Arc<AtomicU64>,
+}
+
+impl<A> LoggedAlloc<A> {
+    /// Starts logging the calls to the allocator
+    #[inline]
+    pub fn new(inner: A, log: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self { inner, log: Arc::new(log), next_stream: Arc::default() }
+    }
+
+    /// Logs the calls to the allocator to stderr
+    #[inline]
+    pub fn stderr(inner: A) -> Self {
+        Self::new(inner, |x| eprintln!("{x}"))
+    }
+
+    /// Logs a call & its result
+    #[inline]
+    fn logged<T>(&self, call: &str, result: Result<T, Error>) -> Result<T, Error> {
+        match &result {
+            Ok(_) => (self.log)(&format!("{call}: ok")),
+            Err(e) => (self.log)(&format!("{call}: {e}")),
+        } result
+    }
+
+    /// Wraps a layer of the inner allocator (logging which stream it got)
+    #[inline]
+    fn wrap<'l, S: LayerStream>(&self, call: &str, layer: Result<Layer<'l, S>, Error>) -> Result<Layer<'l, LoggedStream<S>>, Error> {
+        let id = self.next_stream.fetch_add(1, Ordering::Relaxed);
+        let layer = self.logged(&format!("{call} -> stream {id}"), layer)?;
+        Ok(layer.map_stream(|inner| LoggedStream { inner, log: self.log.clone(), id }))
+    }
+}
+
+/// A layer stream that logs every call to it
+pub struct LoggedStream<S> {
+    /// The wrapped layer stream
+    inner: S,
+    /// Where the log lines go
+    log: Logger,
+    /// The id of the stream in the log
+    id: u64,
+}
+
+impl<S> LoggedStream<S> {
+    /// Logs a call & its result
+    #[inline]
+    fn logged<T: std::fmt::Debug>(&self, call: std::fmt::Arguments, result: std::io::Result<T>) -> std::io::Result<T> {
+        match &result {
+            Ok(x) => (self.log)(&format!("stream {}: {call} -> {x:?}", self.id)),
+            Err(e) => (self.log)(&format!("stream {}: {call}: {e}", self.id)),
+        } result
+    }
+}
+
+impl<S: LayerStream> Read for LoggedStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.logged(format_args!("read({} bytes)", buf.len()), result)
+    }
+}
+
+impl<S: LayerStream> Write for LoggedStream<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.logged(format_args!("write({} bytes)", buf.len()), result)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.inner.flush();
+        self.logged(format_args!("flush"), result)
+    }
+}
+
+impl<S: LayerStream> Seek for LoggedStream<S> {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let result = self.inner.seek(pos);
+        self.logged(format_args!("seek({pos:?})"), result)
+    }
+}
+
+impl<S: LayerStream> LayerStream for LoggedStream<S> {}
+
+impl<'l, A: Allocator<'l>> Allocator<'l> for LoggedAlloc<A> {
+    type LayerStream = LoggedStream<A::LayerStream>;
+
+    fn load_layers(&self) -> Result<Vec<Layer<'l, Self::LayerStream>>, Error> {
+        let layers = self.logged("load_layers", self.inner.load_layers())?;
+        layers.into_iter().map(|x| self.wrap("load_layers", Ok(x))).collect()
+    }
+
+    fn add_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        let layer = self.inner.add_layer();
+        self.wrap("add_layer", layer)
+    }
+
+    fn releases_in_place(&self) -> bool {
+        self.inner.releases_in_place()
+    }
+
+    fn layer_committed(&mut self) -> Result<(), Error> {
+        let result = self.inner.layer_committed();
+        self.logged("layer_committed", result)
+    }
+
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        let result = self.inner.drop_top_layer();
+        self.logged("drop_top_layer", result)
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        let result = self.inner.drop_layer(layer);
+        self.logged(&format!("drop_layer({layer})"), result)
+    }
+
+    fn drop_layers(&mut self, layers: &[usize]) -> Result<(), Error> {
+        let result = self.inner.drop_layers(layers);
+        self.logged(&format!("drop_layers({layers:?})"), result)
+    }
+
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        let result = self.inner.rebase(top_layer);
+        self.logged(&format!("rebase({top_layer})"), result)
+    }
+
+    fn add_replacement_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        let layer = self.inner.add_replacement_layer();
+        self.wrap("add_replacement_layer", layer)
+    }
+
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        let result = self.inner.replace_layers(layers.clone());
+        self.logged(&format!("replace_layers({layers:?})"), result)
+    }
+}
+
+/// The points at which a `FaultyAlloc` can fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    /// `Allocator::load_layers`
+    LoadLayers,
+    /// `Allocator::add_layer`
+    AddLayer,
+    /// `Allocator::drop_top_layer`
+    DropTopLayer,
+    /// `Allocator::drop_layer`
+    DropLayer,
+    /// `Allocator::rebase`
+    Rebase,
+    /// `Allocator::add_replacement_layer`
+    AddReplacementLayer,
+    /// `Allocator::replace_layers`
+    ReplaceLayers,
+    /// A read from a layer stream
+    Read,
+    /// A write to a layer stream
+    Write,
+    /// A seek in a layer stream
+    Seek,
+    /// A flush of a layer stream
+    Flush,
+}
+
+/// How a `FaultyAlloc` fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Fails with an i/o error without doing anything
+    Error,
+    /// Writes only the first bytes of the buffer and then fails (a torn write); acts like `Error` anywhere other than a write
+    ShortWrite(usize),
+}
+
+/// A fault that's waiting to be hit
+#[derive(Debug)]
+struct FaultRule {
+    /// Where the fault happens
+    point: FaultPoint,
+    /// How many more calls at the point get let through before the fault happens
+    skip: u64,
+    /// How it fails
+    fault: Fault,
+}
+
+/// The faults to inject into a `FaultyAlloc` & its layer streams; shared so faults can be injected after the allocator has been handed to a database
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector(Arc<Mutex<Vec<FaultRule>>>);
+
+impl FaultInjector {
+    /// Makes the call at the point fail (once) after letting `skip` calls through
+    #[inline]
+    pub fn inject(&self, point: FaultPoint, skip: u64, fault: Fault) {
+        if let Ok(mut x) = lock(&self.0) { x.push(FaultRule { point, skip, fault }) };
+    }
+
+    /// Removes all the faults that haven't been hit yet
+    #[inline]
+    pub fn clear(&self) {
+        if let Ok(mut x) = lock(&self.0) { x.clear() };
+    }
+
+    /// The amount of faults that haven't been hit yet
+    #[inline]
+    pub fn pending(&self) -> usize {
+        lock(&self.0).map(|x| x.len()).unwrap_or(0)
+    }
+
+    /// Passes a call at the point; returns the fault if it hits one
+    fn trip(&self, point: FaultPoint) -> Option<Fault> {
+        let mut rules = lock(&self.0).ok()?;
+        let i = rules.iter().position(|x| x.point == point)?;
+        if rules[i].skip > 0 {
+            rules[i].skip -= 1;
+            return None;
+        } Some(rules.remove(i).fault)
+    }
+
+    /// Fails the allocator operation if it hits a fault
+    #[inline]
+    fn check(&self, point: FaultPoint) -> Result<(), Error> {
+        match self.trip(point) {
+            Some(_) => Err(Error::IOError(injected(point))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The i/o error of an injected fault
+#[inline]
+fn injected(point: FaultPoint) -> std::io::Error {
+    std::io::Error::other(format!("injected fault at {point:?}"))
+}
+
+/// # Faulty Allocator
+/// ---
+/// Injects i/o errors & short writes into an allocator & its layer streams at configurable points; for testing how a database copes with failing storage
+///
+/// **note:** drops multiple layers one by one (through `drop_layer`) so faults can hit partway through
+#[derive(Debug)]
+pub struct FaultyAlloc<A> {
+    /// The wrapped allocator
+    pub inner: A,
+    /// The faults to inject
+    pub faults: FaultInjector,
+}
+
+impl<A> FaultyAlloc<A> {
+    /// Wraps the allocator with no faults injected yet
+    #[inline]
+    pub fn new(inner: A) -> Self {
+        Self { inner, faults: FaultInjector::default() }
+    }
+
+    /// Wraps a layer of the inner allocator
+    #[inline]
+    fn wrap<'l, S: LayerStream>(&self, layer: Layer<'l, S>) -> Layer<'l, FaultyStream<S>> {
+        layer.map_stream(|inner| FaultyStream { inner, faults: self.faults.clone() })
+    }
+}
+
+/// A layer stream that fails when it hits an injected fault
+#[derive(Debug)]
+pub struct FaultyStream<S> {
+    /// The wrapped layer stream
+    inner: S,
+    /// The faults to inject
+    faults: FaultInjector,
+}
+
+impl<S: LayerStream> Read for FaultyStream<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.faults.trip(FaultPoint::Read).is_some() { return Err(injected(FaultPoint::Read)) };
+        self.inner.read(buf)
+    }
+}
+
+impl<S: LayerStream> Write for FaultyStream<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.faults.trip(FaultPoint::Write) {
+            Some(Fault::ShortWrite(len)) => {
+                self.inner.write_all(&buf[..std::cmp::min(len, buf.len())])?;
+                Err(injected(FaultPoint::Write))
+            },
+            Some(Fault::Error) => Err(injected(FaultPoint::Write)),
+            None => self.inner.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.faults.trip(FaultPoint::Flush).is_some() { return Err(injected(FaultPoint::Flush)) };
+        self.inner.flush()
+    }
+}
+
+impl<S: LayerStream> Seek for FaultyStream<S> {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        if self.faults.trip(FaultPoint::Seek).is_some() { return Err(injected(FaultPoint::Seek)) };
+        self.inner.seek(pos)
+    }
+}
+
+impl<S: LayerStream> LayerStream for FaultyStream<S> {}
+
+impl<'l, A: Allocator<'l>> Allocator<'l> for FaultyAlloc<A> {
+    type LayerStream = FaultyStream<A::LayerStream>;
+
+    fn load_layers(&self) -> Result<Vec<Layer<'l, Self::LayerStream>>, Error> {
+        self.faults.check(FaultPoint::LoadLayers)?;
+        Ok(self.inner.load_layers()?.into_iter().map(|x| self.wrap(x)).collect())
+    }
+
+    fn add_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        self.faults.check(FaultPoint::AddLayer)?;
+        let layer = self.inner.add_layer()?;
+        Ok(self.wrap(layer))
+    }
+
+    fn releases_in_place(&self) -> bool {
+        self.inner.releases_in_place()
+    }
+
+    fn layer_committed(&mut self) -> Result<(), Error> {
+        self.inner.layer_committed()
+    }
+
+    fn drop_top_layer(&mut self) -> Result<(), Error> {
+        self.faults.check(FaultPoint::DropTopLayer)?;
+        self.inner.drop_top_layer()
+    }
+
+    fn drop_layer(&mut self, layer: usize) -> Result<(), Error> {
+        self.faults.check(FaultPoint::DropLayer)?;
+        self.inner.drop_layer(layer)
+    }
+
+    fn rebase(&mut self, top_layer: usize) -> Result<(), Error> {
+        self.faults.check(FaultPoint::Rebase)?;
+        self.inner.rebase(top_layer)
+    }
+
+    fn add_replacement_layer(&mut self) -> Result<Layer<'l, Self::LayerStream>, Error> {
+        self.faults.check(FaultPoint::AddReplacementLayer)?;
+        let layer = self.inner.add_replacement_layer()?;
+        Ok(self.wrap(layer))
+    }
+
+    fn replace_layers(&mut self, layers: Range<usize>) -> Result<(), Error> {
+        self.faults.check(FaultPoint::ReplaceLayers)?;
+        self.inner.replace_layers(layers)
+    }
+}
@@ -13,8 +13,14 @@ pub enum Error {
     ReadOnly,
     /// When the layer meta-data is invalid
     InvalidLayer,
+    /// When the manifest of a directory database is invalid
+    InvalidManifest,
     /// When there is an out of bounds read
     OutOfBounds,
+    /// When a savepoint doesn't exist (released, rolled back past or committed)
+    InvalidSavepoint,
+    /// When a transaction conflicts with newer writes to the database
+    Conflict,
     /// A custom error
     Custom(String),
 }
@@ -8,7 +8,7 @@
 //! ```rust
 //! use stack_db::prelude::*;
 //! 
-//! let allocator = SkdbMemAlloc; // or `SkdbDiskAlloc::new()`
+//! let allocator = SkdbMemAlloc::new(); // or `SkdbDiskAlloc::new()`
 //! let mut database = StackDB::new(allocator).unwrap();
 //! 
 //! // writing
@@ -1,7 +1,7 @@
 use stack_db::prelude::*;
 
 fn main() {
-    let allocator = SkdbDirAlloc::new("db.skdb").unwrap(); // or `SkdbDiskAlloc::new()`
+    let allocator = if std::path::Path::new("db.skdb").exists() { SkdbDirAlloc::load("db.skdb") } else { SkdbDirAlloc::new("db.skdb") }.unwrap(); // or `SkdbDiskAlloc::new()`
     let mut database = StackDB::new(allocator).unwrap();
 
     // writing
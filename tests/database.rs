@@ -1,10 +1,13 @@
 //! base-database tests
 
-use stack_db::{base::database::StackDB, default::alloc::SkdbMemAlloc};
+use std::{io::Write, sync::{Arc, Mutex}, time::Duration};
+use stack_db::{base::database::{allocator::Allocator, compaction::{spawn_compactor, CompactionMode, CompactionPolicy}, retention::RetentionPolicy, stats::LayerStats, StackDB}, default::{alloc::{SkdbDirAlloc, SkdbFileAlloc, SkdbMemAlloc, SkdbTieredAlloc}, middleware::{Fault, FaultPoint, FaultyAlloc, LoggedAlloc, MetricsAlloc}}, errors::Error};
+#[cfg(feature = "mmap")]
+use stack_db::default::alloc::SkdbMmapAlloc;
 
 #[test]
 fn database_read_write() {
-    let mut db = StackDB::new(SkdbMemAlloc).unwrap();
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
 
     // write tests
     db.write(14, b"Hello, ").unwrap();
@@ -20,3 +23,763 @@ fn database_read_write() {
     db.rebase(256).unwrap();
     assert_eq!(&*db.read(14..27).unwrap(), b"hello, world!");
 }
+
+#[test]
+fn database_savepoints() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+
+    db.write(0, b"hello, world").unwrap();
+    let outer = db.savepoint().unwrap();
+    db.write(7, b"there").unwrap();
+    let inner = db.savepoint().unwrap();
+    db.write(0, b"HELLO").unwrap();
+    db.write(12, b"!").unwrap();
+    assert_eq!(&*db.read(0..13).unwrap(), b"HELLO, there!");
+
+    // undo the inner writes only
+    db.rollback_to(inner).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+    assert!(db.read(0..13).is_err());
+
+    // release keeps the writes but drops the savepoint
+    db.write(0, b"H").unwrap();
+    db.release(inner).unwrap();
+    assert!(db.rollback_to(inner).is_err());
+    assert_eq!(&*db.read(0..12).unwrap(), b"Hello, there");
+
+    db.rollback_to(outer).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, world");
+    db.commit().unwrap();
+    assert!(db.rollback_to(outer).is_err());
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, world");
+
+    // savepoints of committed writes don't carry over to the next heap layer
+    db.write(0, b"bbbb").unwrap();
+    let next = db.savepoint().unwrap();
+    db.write(0, b"cccc").unwrap();
+    assert!(db.rollback_to(outer).is_err() && db.release(outer).is_err());
+    db.rollback_to(next).unwrap();
+    assert_eq!(&*db.read(0..4).unwrap(), b"bbbb");
+}
+
+#[test]
+fn database_savepoint_splits() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+
+    // a later write splits an earlier one made after the same savepoint
+    let savepoint = db.savepoint().unwrap();
+    db.write(10, &[1; 10]).unwrap();
+    db.write(5, &[2; 10]).unwrap();
+    db.rollback_to(savepoint).unwrap();
+    assert!(db.read(15..20).is_err());
+
+    db.write(100, b"hello").unwrap();
+    db.commit().unwrap();
+    assert_eq!(&*db.read(100..105).unwrap(), b"hello");
+    assert!(db.read(10..20).is_err());
+}
+
+#[test]
+fn database_transactions() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, &[0; 8]).unwrap();
+    db.commit().unwrap();
+
+    // compare-and-swap
+    assert!(db.compare_and_swap(0, &[0; 4], &[1; 4]).unwrap());
+    assert!(!db.compare_and_swap(0, &[0; 4], &[2; 4]).unwrap());
+    assert!(!db.compare_and_swap(6, &[0; 4], &[2; 4]).unwrap()); // partly unwritten
+    assert_eq!(&*db.read(0..4).unwrap(), &[1; 4]);
+    db.commit().unwrap();
+
+    // non-conflicting transactions
+    let mut a = db.transaction();
+    let mut b = db.transaction();
+    assert_eq!(&*a.read(&mut db, 0..4).unwrap(), &[1; 4]);
+    a.write(0, &[3; 4]).unwrap();
+    assert_eq!(&*a.read(&mut db, 0..4).unwrap(), &[3; 4]);
+    assert_eq!(&*b.read(&mut db, 4..8).unwrap(), &[0; 4]);
+    b.write(4, &[4; 4]).unwrap();
+    db.commit_transaction(a).unwrap();
+    db.commit_transaction(b).unwrap();
+    assert_eq!(&*db.read(0..8).unwrap(), &[3, 3, 3, 3, 4, 4, 4, 4]);
+
+    // conflicting transactions
+    let mut a = db.transaction();
+    let mut b = db.transaction();
+    a.read(&mut db, 0..8).unwrap();
+    a.write(0, &[5; 8]).unwrap();
+    b.read(&mut db, 2..3).unwrap();
+    b.write(2, &[6]).unwrap();
+    db.commit_transaction(b).unwrap();
+    assert!(matches!(db.commit_transaction(a), Err(Error::Conflict)));
+    assert_eq!(&*db.read(0..8).unwrap(), &[3, 3, 6, 3, 4, 4, 4, 4]);
+
+    // uncommitted writes from before a read aren't newer than it; ones after it are
+    db.write(0, &[7; 4]).unwrap();
+    let mut a = db.transaction();
+    let mut b = db.transaction();
+    assert_eq!(&*b.read(&mut db, 0..4).unwrap(), &[7; 4]);
+    assert_eq!(&*a.read(&mut db, 0..4).unwrap(), &[7; 4]);
+    a.write(100, &[8]).unwrap();
+    db.commit_transaction(a).unwrap();
+    db.write(2, &[9]).unwrap();
+    assert!(matches!(db.commit_transaction(b), Err(Error::Conflict)));
+}
+
+#[test]
+fn database_copy() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+
+    // copies of committed & uncommitted data
+    db.write(12, b"!").unwrap();
+    db.copy(7..13, 100).unwrap();
+    assert_eq!(&*db.read(100..106).unwrap(), b"world!");
+    db.commit().unwrap();
+    db.write(0, b"HELLO").unwrap();
+    db.commit().unwrap();
+    assert_eq!(&*db.read(100..106).unwrap(), b"world!");
+
+    // overlapping move
+    db.move_range(100..106, 102).unwrap();
+    assert_eq!(&*db.read(102..108).unwrap(), b"world!");
+    db.commit().unwrap();
+    assert_eq!(&*db.read(102..108).unwrap(), b"world!");
+    assert!(db.read(100..102).is_err());
+
+    // copies of nothing stay nothing
+    db.copy(200..210, 300).unwrap();
+    assert!(db.read(300..301).is_err());
+}
+
+#[test]
+fn database_delete() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+
+    db.delete(5..7).unwrap();
+    assert!(db.read(0..12).is_err());
+    assert_eq!(&*db.read(0..5).unwrap(), b"hello");
+    db.commit().unwrap();
+    assert!(db.read(4..6).is_err());
+    assert_eq!(&*db.read(7..12).unwrap(), b"world");
+
+    // moves leave nothing behind
+    db.move_range(7..12, 9).unwrap();
+    assert!(db.read(7..9).is_err());
+    assert_eq!(&*db.read(9..14).unwrap(), b"world");
+
+    // rebasing drops the deleted data
+    db.rebase(256).unwrap();
+    assert!(db.read(5..6).is_err());
+    assert!(db.read(7..9).is_err());
+    assert_eq!(&*db.read(0..5).unwrap(), b"hello");
+    assert_eq!(&*db.read(9..14).unwrap(), b"world");
+
+    // even out of a single layer (with an empty heap layer left over from a rollback)
+    db.delete(0..2).unwrap();
+    db.commit().unwrap();
+    db.rebase(256).unwrap();
+    let savepoint = db.savepoint().unwrap();
+    db.write(0, b"he").unwrap();
+    db.rollback_to(savepoint).unwrap();
+    db.rebase(256).unwrap();
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.layers.len(), stats.stored), (1, 2 * 17 + 3 + 5)); // just "llo" & "world"
+    assert!(db.read(0..2).is_err());
+    assert_eq!(&*db.read(2..5).unwrap(), b"llo");
+}
+
+#[test]
+fn database_fill() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+
+    // a terabyte of zeros
+    db.fill(0..1 << 40, 0).unwrap();
+    db.write(1 << 20, b"hello").unwrap();
+    assert_eq!(&*db.read((1 << 20) - 2..(1 << 20) + 7).unwrap(), b"\0\0hello\0\0");
+    db.commit().unwrap();
+    assert_eq!(&*db.read((1 << 39)..(1 << 39) + 4).unwrap(), &[0; 4]);
+
+    // fills survive rebases
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.fill(0..4096, 0).unwrap();
+    db.fill(8..16, 0xff).unwrap();
+    db.rebase(256).unwrap();
+    assert_eq!(&*db.read(6..18).unwrap(), &[0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+    assert!(db.read(4095..4097).is_err());
+}
+
+#[test]
+fn database_compact() {
+    let path = std::env::temp_dir().join("stack-db-test-compact");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbDirAlloc::new(&path).unwrap()).unwrap();
+
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.write(0, b"H").unwrap();
+    db.copy(0..5, 100).unwrap();
+    db.commit().unwrap();
+    db.delete(5..7).unwrap();
+    db.fill(200..300, 1).unwrap();
+    db.commit().unwrap();
+    db.write(7, b"W").unwrap();
+    db.commit().unwrap();
+
+    // merge the middle two layers
+    db.compact(1..3).unwrap();
+    assert_eq!(&*db.read(0..5).unwrap(), b"Hello");
+    assert_eq!(&*db.read(7..12).unwrap(), b"World");
+    assert_eq!(&*db.read(100..105).unwrap(), b"Hello");
+    assert_eq!(&*db.read(250..251).unwrap(), &[1]);
+    assert!(db.read(5..6).is_err());
+
+    // and everything after reloading
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(100..105).unwrap(), b"Hello");
+    db.compact(0..3).unwrap();
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..5).unwrap(), b"Hello");
+    assert_eq!(&*db.read(7..12).unwrap(), b"World");
+    assert_eq!(&*db.read(100..105).unwrap(), b"Hello");
+    assert!(db.read(5..6).is_err());
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 2); // the layer & the manifest
+}
+
+#[test]
+fn database_auto_compaction() {
+    let path = std::env::temp_dir().join("stack-db-test-auto-compaction");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbDirAlloc::new(&path).unwrap()).unwrap();
+    db.set_compaction_policy(Some(CompactionPolicy { max_layers: Some(3), ..Default::default() }));
+
+    // inline
+    for i in 0..4u8 {
+        db.write(i as u64, &[i]).unwrap();
+        db.commit().unwrap();
+    }
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 2); // the layer & the manifest
+    assert_eq!(&*db.read(0..4).unwrap(), &[0, 1, 2, 3]);
+
+    // space amplification
+    db.set_compaction_policy(Some(CompactionPolicy { max_space_amplification: Some(1.5), ..Default::default() }));
+    db.write(0, &[4; 3]).unwrap();
+    db.commit().unwrap();
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 3); // two layers & the manifest
+    db.write(0, &[5; 2]).unwrap();
+    db.commit().unwrap();
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 2); // the layer & the manifest
+    assert_eq!(&*db.read(0..4).unwrap(), &[5, 5, 4, 3]);
+
+    // background
+    db.set_compaction_policy(Some(CompactionPolicy { max_commits: Some(1), mode: CompactionMode::Background, ..Default::default() }));
+    let db = Arc::new(Mutex::new(db));
+    for i in 0..2u8 {
+        let mut db = db.lock().unwrap();
+        db.write(i as u64, &[i]).unwrap();
+        db.commit().unwrap();
+    }
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 4);
+    let compactor = spawn_compactor(&db, Duration::from_millis(1));
+    while std::fs::read_dir(&path).unwrap().count() != 2 { std::thread::yield_now() };
+    assert_eq!(&*db.lock().unwrap().read(0..4).unwrap(), &[0, 1, 4, 3]);
+
+    drop(db);
+    compactor.join().unwrap().unwrap();
+}
+
+#[test]
+fn database_stats() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.write(0, b"HELLO").unwrap();
+    db.copy(7..12, 100).unwrap();
+    db.commit().unwrap();
+    db.write(1000, b"!").unwrap();
+    db.write(7, b"W").unwrap();
+
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.layers.len(), 3);
+    assert_eq!(stats.layers[0], LayerStats { size: 12, bounds: Some(0..12), live: 7, shadowed: 5, ..stats.layers[0].clone() });
+    assert_eq!(stats.layers[1], LayerStats { size: 10, bounds: Some(0..105), live: 10, shadowed: 0, ..stats.layers[1].clone() });
+    assert_eq!(stats.layers[2], LayerStats { size: 2, bounds: Some(7..1001), live: 2, shadowed: 0, ..stats.layers[2].clone() });
+    assert_eq!((stats.size, stats.live, stats.shadowed), (24, 19, 5));
+    assert_eq!(stats.layers[0].stored, 17 + 12);
+    assert_eq!(stats.stored_live + stats.stored_shadowed, stats.stored);
+
+    // huge fills hardly take up any space
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.fill(0..1 << 30, 0).unwrap();
+    db.commit().unwrap();
+    db.fill(0..1 << 30, 1).unwrap();
+    db.commit().unwrap();
+    let stats = db.stats().unwrap();
+    assert_eq!((stats.shadowed, stats.stored_shadowed, stats.stored_live), (1 << 30, 17 + 1, 17 + 1));
+}
+
+#[test]
+fn database_retention() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    for i in 0..6u8 {
+        db.write(i as u64, &[i]).unwrap();
+        db.commit().unwrap();
+    }
+    assert!(db.stats().unwrap().layers.iter().all(|x| x.timestamp > 0));
+
+    // all the older commits are from the same day
+    db.retain(&RetentionPolicy { keep_last: 2, snapshot_interval: Some(Duration::from_secs(60 * 60 * 24)) }).unwrap();
+    assert_eq!(db.stats().unwrap().layers.len(), 3);
+    assert_eq!(&*db.read(0..6).unwrap(), &[0, 1, 2, 3, 4, 5]);
+
+    db.retain(&RetentionPolicy::default()).unwrap();
+    assert_eq!(db.stats().unwrap().layers.len(), 1);
+    assert_eq!(&*db.read(0..6).unwrap(), &[0, 1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn database_streaming_rebase() {
+    let path = std::env::temp_dir().join("stack-db-test-streaming-rebase");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbDirAlloc::new(&path).unwrap()).unwrap();
+
+    db.write(0, &[0; 64]).unwrap();
+    db.commit().unwrap();
+    db.write(0, &[1; 16]).unwrap();
+    db.commit().unwrap();
+    db.write(100, &[2; 64]).unwrap();
+    db.copy(0..32, 200).unwrap();
+    db.commit().unwrap();
+    db.write(16, &[3; 16]).unwrap();
+    db.fill(1000..1 << 40, 4).unwrap();
+
+    // release old layers as early as possible
+    db.rebase_streaming(0).unwrap();
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 2); // the layer & the manifest
+
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..64).unwrap(), &[[1; 16], [3; 16], [0; 16], [0; 16]].concat());
+    assert_eq!(&*db.read(100..164).unwrap(), &[2; 64]);
+    assert_eq!(&*db.read(200..232).unwrap(), &[[1; 16], [0; 16]].concat());
+    assert_eq!(&*db.read((1 << 40) - 1..1 << 40).unwrap(), &[4]);
+    assert!(std::fs::read_dir(&path).unwrap().all(|x| x.unwrap().metadata().unwrap().len() < 1024));
+}
+
+#[test]
+fn database_rebase_range() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.copy(0..5, 100).unwrap();
+    db.write(50, b"cold").unwrap();
+    db.commit().unwrap();
+    db.write(0, b"HELLO").unwrap();
+    db.commit().unwrap();
+    db.write(2, b"y").unwrap();
+
+    db.rebase_range(0..6).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"HEyLO, world");
+    assert_eq!(&*db.read(100..105).unwrap(), b"hello");
+    assert_eq!(&*db.read(50..54).unwrap(), b"cold");
+
+    // the older layers don't hold the range anymore (and the ones with nothing left get dropped)
+    let stats = db.stats().unwrap();
+    assert_eq!(stats.layers.iter().map(|x| (x.size, x.bounds.clone())).collect::<Vec<_>>(), [(6, Some(6..12)), (9, Some(50..105)), (6, Some(0..6))]);
+    assert_eq!(stats.shadowed, 0);
+
+    // rebasing a range with no data in it leaves the layers alone
+    let tx = db.transaction();
+    db.rebase_range(200..300).unwrap();
+    assert_eq!(db.stats().unwrap().layers.len(), 3);
+    db.commit_transaction(tx).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"HEyLO, world");
+}
+
+#[test]
+fn database_random_writes() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    let mut expected = vec![0u8; 1 << 16];
+    db.write(0, &expected).unwrap();
+
+    // scattered overlapping writes into the same heap layer
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    for i in 0..100_000u32 {
+        seed ^= seed << 13; seed ^= seed >> 7; seed ^= seed << 17;
+        let addr = (seed % (expected.len() as u64 - 4)) as usize;
+        expected[addr..addr+4].copy_from_slice(&i.to_be_bytes());
+        db.write(addr as u64, &i.to_be_bytes()).unwrap();
+    }
+
+    assert_eq!(&*db.read(0..1 << 16).unwrap(), &expected[..]);
+    db.commit().unwrap();
+    assert_eq!(&*db.read(0..1 << 16).unwrap(), &expected[..]);
+}
+
+#[test]
+fn database_sequential_reads() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    for i in 0..4096u64 {
+        db.write(i * 8, &i.to_be_bytes()).unwrap();
+    } db.commit().unwrap();
+
+    // forwards through the read cursor, then jumping backwards
+    for i in 0..4096u64 {
+        assert_eq!(&*db.read(i * 8..i * 8 + 8).unwrap(), &i.to_be_bytes());
+    }
+    for i in (0..4096u64).rev().step_by(7) {
+        assert_eq!(&*db.read(i * 8..i * 8 + 8).unwrap(), &i.to_be_bytes());
+    }
+}
+
+#[test]
+fn database_read_many() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.write(100, b"goodbye").unwrap();
+    db.commit().unwrap();
+    db.fill(200..204, b'z').unwrap();
+
+    let out = db.read_many(&[100..104, 7..12, 200..204, 0..5]).unwrap();
+    assert_eq!(out.iter().map(|x| &**x).collect::<Vec<_>>(), [&b"good"[..], b"world", b"zzzz", b"hello"]);
+    assert!(db.read_many(&[0..5, 50..60]).is_err());
+
+    // overlapping & empty ranges
+    let out = db.read_many(&[3..9, 0..5, 4..4, 102..107]).unwrap();
+    assert_eq!(out.iter().map(|x| &**x).collect::<Vec<_>>(), [&b"lo, wo"[..], b"hello", b"", b"odbye"]);
+}
+
+#[test]
+fn database_read_into() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+
+    let mut buffer = [0u8; 5];
+    db.read_into(7..12, &mut buffer).unwrap();
+    assert_eq!(&buffer, b"world");
+    assert!(db.read_into(0..4, &mut buffer).is_err());
+
+    // single uncommitted writes are lent out of the heap layer
+    assert!(matches!(db.read_cow(0..5).unwrap(), std::borrow::Cow::Borrowed(b"hello")));
+    db.commit().unwrap();
+    assert!(matches!(db.read_cow(0..5).unwrap(), std::borrow::Cow::Owned(x) if x == b"hello"));
+}
+
+#[test]
+fn database_cache() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.set_cache_budget(8192);
+    db.write(0, &[1; 12288]).unwrap();
+    db.commit().unwrap();
+
+    // the first read misses, the second one hits
+    assert_eq!(&*db.read(0..8).unwrap(), &[1; 8]);
+    assert_eq!((db.cache_stats().hits, db.cache_stats().misses), (0, 1));
+    assert_eq!(&*db.read(8..16).unwrap(), &[1; 8]);
+    assert_eq!((db.cache_stats().hits, db.cache_stats().misses), (1, 1));
+
+    // stays within the budget
+    assert_eq!(&*db.read(0..12288).unwrap(), &[1; 12288]);
+    assert!(db.cache_stats().size <= 8192);
+
+    // rewriting the layers drops the cached data
+    db.write(0, &[2; 8]).unwrap();
+    db.commit().unwrap();
+    db.rebase(256).unwrap();
+    assert_eq!(db.cache_stats().size, 0);
+    assert_eq!(&*db.read(0..16).unwrap(), &[2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn database_resolved_map() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    let mut expected = vec![0u8; 256];
+    db.write(0, &expected).unwrap();
+    db.commit().unwrap();
+
+    // reads in between commits keep the resolved map up to date
+    for i in 1..50u8 {
+        let addr = (i as usize * 37) % 240;
+        expected[addr..addr+16].fill(i);
+        db.write(addr as u64, &[i; 16]).unwrap();
+        assert_eq!(&*db.read(0..256).unwrap(), &expected[..]);
+        db.commit().unwrap();
+        assert_eq!(&*db.read(0..256).unwrap(), &expected[..]);
+    }
+
+    db.compact(10..40).unwrap();
+    assert_eq!(&*db.read(0..256).unwrap(), &expected[..]);
+    db.delete(0..8).unwrap();
+    db.commit().unwrap();
+    assert!(db.read(0..8).is_err());
+    assert_eq!(&*db.read(8..256).unwrap(), &expected[8..]);
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn database_mmap() {
+    let path = std::env::temp_dir().join("stack-db-test-mmap");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbMmapAlloc::new(&path).unwrap()).unwrap();
+
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.write(7, b"there").unwrap();
+    db.commit().unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+
+    // same directory format as the directory allocator
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+    let mut db = StackDB::new(SkdbMmapAlloc::load(&path).unwrap()).unwrap();
+    db.rebase(256).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+}
+
+#[test]
+fn database_single_file() {
+    let dir = std::env::temp_dir().join("stack-db-test-single-file");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("db.skdb");
+    let mut db = StackDB::new(SkdbFileAlloc::new(&path).unwrap()).unwrap();
+
+    for i in 0..8u8 {
+        db.write(i as u64 * 4, &[i; 8]).unwrap();
+        db.commit().unwrap();
+    }
+    db.compact(2..6).unwrap();
+    let expected = [[0; 4], [1; 4], [2; 4], [3; 4], [4; 4], [5; 4], [6; 4], [7; 4], [7; 4]].concat();
+    assert_eq!(&*db.read(0..36).unwrap(), &expected[..]);
+
+    // a torn commit at the end of the file gets truncated off
+    let mut db = StackDB::new(SkdbFileAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..36).unwrap(), &expected[..]);
+    drop(db);
+    let len = std::fs::metadata(&path).unwrap().len();
+    std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0, 0, 0, 1, 2]).unwrap();
+    let mut db = StackDB::new(SkdbFileAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+    // rebasing keeps it to one file (releasing layers early would rewrite the file over & over)
+    db.write(100, b"hello").unwrap();
+    assert!(db.rebase_streaming(0).is_err());
+    db.rebase(256).unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    assert_eq!(&*db.read(0..36).unwrap(), &expected[..]);
+    let mut db = StackDB::new(SkdbFileAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..36).unwrap(), &expected[..]);
+    assert_eq!(&*db.read(100..105).unwrap(), b"hello");
+    drop(db);
+
+    // there's nothing to replace the layers with
+    let mut alloc = SkdbFileAlloc::load(&path).unwrap();
+    assert!(matches!(alloc.replace_layers(0..1), Err(Error::OutOfBounds)));
+    assert_eq!(&*StackDB::new(alloc).unwrap().read(100..105).unwrap(), b"hello");
+}
+
+#[test]
+fn database_mem_round_trip() {
+    let mut db = StackDB::new(SkdbMemAlloc::new()).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+    db.write(7, b"there").unwrap();
+    db.commit().unwrap();
+    db.write(0, b"uncommitted").unwrap();
+
+    // only the committed layers get saved
+    let bytes = db.alloc().to_bytes().unwrap();
+    let mut db = StackDB::new(SkdbMemAlloc::from_bytes(&bytes).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+    db.rebase(256).unwrap();
+    assert!(db.alloc().to_bytes().unwrap().len() < bytes.len());
+
+    // same layer format as the directory allocator
+    let path = std::env::temp_dir().join("stack-db-test-mem-export");
+    let _ = std::fs::remove_dir_all(&path);
+    db.alloc().export(&path).unwrap();
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+}
+
+#[test]
+fn database_tiered() {
+    let path = std::env::temp_dir().join("stack-db-test-tiered");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbTieredAlloc::new(&path, 1024).unwrap()).unwrap();
+
+    for i in 0..8u8 {
+        db.write(i as u64 * 4, &[i; 256]).unwrap();
+        db.commit().unwrap();
+    }
+    let expected = [&[[0; 4], [1; 4], [2; 4], [3; 4], [4; 4], [5; 4], [6; 4]].concat()[..], &[7; 256]].concat();
+    assert_eq!(&*db.read(0..284).unwrap(), &expected[..]);
+
+    // older layers got spilled to disk below the recent ones in memory
+    let spilled = db.alloc().disk.manifest().layers.len();
+    assert!(spilled > 0 && spilled < 8);
+    assert!(db.alloc().memory_size().unwrap() <= 1024);
+
+    // compacting across both tiers keeps the order
+    db.compact(spilled - 1..spilled + 1).unwrap();
+    assert_eq!(&*db.read(0..284).unwrap(), &expected[..]);
+
+    // spilling everything persists the database
+    db.alloc_mut().spill(0).unwrap();
+    assert_eq!(db.alloc().memory_size().unwrap(), 0);
+    let mut db = StackDB::new(SkdbTieredAlloc::load(&path, 1024).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..284).unwrap(), &expected[..]);
+    db.rebase(256).unwrap();
+    assert_eq!(&*db.read(0..284).unwrap(), &expected[..]);
+}
+
+#[test]
+fn database_middleware() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let lines = log.clone();
+    let alloc = MetricsAlloc::new(LoggedAlloc::new(SkdbMemAlloc::new(), move |x| lines.lock().unwrap().push(x.to_string())));
+    let mut db = StackDB::new(alloc).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+
+    let metrics = db.alloc().metrics();
+    assert_eq!((metrics.loads, metrics.layers_added), (1, 1));
+    assert!(metrics.bytes_written > 12 && metrics.flushes == 1);
+    assert!(log.lock().unwrap().iter().any(|x| x == "add_layer -> stream 0: ok"));
+}
+
+#[test]
+fn database_faults() {
+    let mut db = StackDB::new(FaultyAlloc::new(SkdbMemAlloc::new())).unwrap();
+    db.write(0, b"hello, world").unwrap();
+    db.commit().unwrap();
+
+    // a torn commit keeps the writes on the heap so it can be retried
+    db.write(7, b"there").unwrap();
+    db.alloc().faults.inject(FaultPoint::Write, 0, Fault::ShortWrite(10));
+    assert!(matches!(db.commit(), Err(Error::IOError(_))));
+    assert_eq!(&*db.read(0..12).unwrap(), b"hello, there");
+    let bytes = db.alloc().inner.to_bytes().unwrap();
+    assert_eq!(&*StackDB::new(SkdbMemAlloc::from_bytes(&bytes).unwrap()).unwrap().read(0..12).unwrap(), b"hello, world");
+    db.commit().unwrap();
+
+    // a failed inline compaction doesn't fail the (durable) commit before it
+    db.set_compaction_policy(Some(CompactionPolicy { max_layers: Some(1), ..Default::default() }));
+    db.write(0, b"HELLO").unwrap();
+    db.alloc().faults.inject(FaultPoint::ReplaceLayers, 0, Fault::Error);
+    db.commit().unwrap();
+    assert!(db.take_compaction_error().is_some() && db.take_compaction_error().is_none());
+    assert_eq!(db.stats().unwrap().layers.len(), 3);
+    let bytes = db.alloc().inner.to_bytes().unwrap();
+    assert_eq!(&*StackDB::new(SkdbMemAlloc::from_bytes(&bytes).unwrap()).unwrap().read(0..12).unwrap(), b"HELLO, there");
+    db.set_compaction_policy(None);
+
+    // a failed rebase leaves the old layers in place
+    for point in [FaultPoint::Write, FaultPoint::ReplaceLayers] {
+        db.alloc().faults.inject(point, 0, Fault::Error);
+        assert!(db.rebase(256).is_err());
+        assert_eq!(&*db.read(0..12).unwrap(), b"HELLO, there");
+    }
+    assert_eq!(db.alloc().faults.pending(), 0);
+    db.rebase(256).unwrap();
+    let bytes = db.alloc().inner.to_bytes().unwrap();
+    assert_eq!(&*StackDB::new(SkdbMemAlloc::from_bytes(&bytes).unwrap()).unwrap().read(0..12).unwrap(), b"HELLO, there");
+
+    // so does a streaming rebase that fails partway through releasing the old layers
+    let path = std::env::temp_dir().join("stack-db-test-faults");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(FaultyAlloc::new(SkdbDirAlloc::new(&path).unwrap())).unwrap();
+    for i in 0..6u8 {
+        db.write(i as u64 * 32, &[i; 64]).unwrap();
+        db.commit().unwrap();
+    }
+    let expected = db.read(0..224).unwrap();
+    db.alloc().faults.inject(FaultPoint::DropLayer, 2, Fault::Error);
+    assert!(db.rebase_streaming(0).is_err());
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(db.read(0..224).unwrap(), expected);
+    db.rebase(256).unwrap();
+    assert_eq!(db.read(0..224).unwrap(), expected);
+}
+
+#[test]
+fn database_manifest() {
+    let path = std::env::temp_dir().join("stack-db-test-manifest");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut db = StackDB::new(SkdbDirAlloc::new(&path).unwrap()).unwrap();
+    for i in 0..3u8 {
+        db.write(i as u64, &[i; 4]).unwrap();
+        db.commit().unwrap();
+    }
+    db.alloc_mut().tag(1, "v1").unwrap();
+    db.alloc_mut().set_setting("owner", "tests").unwrap();
+    db.write(0, b"uncommitted").unwrap();
+    drop(db);
+
+    // an existing database doesn't get overwritten by a new one
+    assert!(SkdbDirAlloc::new(&path).is_err());
+
+    // stray files are left alone (their ids get skipped) & the uncommitted top layer gets dropped
+    std::fs::write(path.join("99"), b"stray").unwrap();
+    std::fs::write(path.join("4"), b"stray").unwrap();
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(db.alloc().manifest().layers, [0, 1, 2]);
+    assert_eq!((db.alloc().tagged("v1"), db.alloc().setting("owner")), (Some(1), Some("tests")));
+    assert_eq!(&*db.read(0..6).unwrap(), &[0, 1, 2, 2, 2, 2]);
+
+    // tags follow their layer through compactions & rebases; ids never get reused
+    db.compact(0..2).unwrap();
+    assert_eq!((db.alloc().manifest().layers.as_slice(), db.alloc().tagged("v1")), (&[5, 2][..], Some(0)));
+    db.rebase(256).unwrap();
+    assert_eq!((db.alloc().manifest().layers.as_slice(), db.alloc().tagged("v1")), (&[6][..], Some(0)));
+    assert_eq!(&*db.read(0..6).unwrap(), &[0, 1, 2, 2, 2, 2]);
+    assert_eq!(std::fs::read(path.join("4")).unwrap(), b"stray");
+
+    drop(db);
+
+    // layers of an unknown format version get rejected instead of dropped as torn
+    let mut layer = std::fs::read(path.join("6")).unwrap();
+    layer[7] = 2;
+    std::fs::write(path.join("6"), &layer).unwrap();
+    assert!(matches!(SkdbDirAlloc::load(&path), Err(Error::InvalidLayer)));
+    assert!(path.join("6").exists());
+
+    // directories without a manifest get one built from their numbered layers
+    layer[7] = 1;
+    std::fs::write(path.join("6"), &layer).unwrap();
+    for file in ["MANIFEST", "4", "99"] { std::fs::remove_file(path.join(file)).unwrap() };
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!((db.alloc().manifest().layers.as_slice(), db.alloc().manifest().next_id), (&[6][..], 7));
+    assert_eq!(&*db.read(0..6).unwrap(), &[0, 1, 2, 2, 2, 2]);
+    drop(db);
+
+    // layers from before the format was versioned get upgraded (with a torn top one dropped)
+    let path = std::env::temp_dir().join("stack-db-test-legacy");
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    let legacy = |sections: &[(u64, &[u8])]| {
+        let size = sections.iter().map(|(_, x)| x.len() as u64).sum::<u64>();
+        let (start, end) = (sections.iter().map(|x| x.0).min().unwrap(), sections.iter().map(|(i, x)| i + x.len() as u64).max().unwrap());
+        let mut bytes = [size, start, end].map(u64::to_be_bytes).concat();
+        for (idx, data) in sections {
+            bytes.extend_from_slice(&[*idx, idx + data.len() as u64].map(u64::to_be_bytes).concat());
+            bytes.extend_from_slice(data);
+        } bytes
+    };
+    std::fs::write(path.join("0"), legacy(&[(0, b"hello"), (8, b"world")])).unwrap();
+    std::fs::write(path.join("1"), legacy(&[(1, b"ipp")])).unwrap();
+    std::fs::write(path.join("2"), &legacy(&[(0, b"torn")])[..30]).unwrap();
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(db.alloc().manifest().layers, [0, 1]);
+    assert_eq!((&*db.read(0..5).unwrap(), &*db.read(8..13).unwrap()), (&b"hippo"[..], &b"world"[..]));
+    db.write(0, b"H").unwrap();
+    db.commit().unwrap();
+    assert_eq!(db.alloc().manifest().layers, [0, 1, 3]);
+    drop(db);
+    let mut db = StackDB::new(SkdbDirAlloc::load(&path).unwrap()).unwrap();
+    assert_eq!(&*db.read(0..5).unwrap(), b"Hippo");
+}
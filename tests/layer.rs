@@ -1,5 +1,5 @@
 use std::{borrow::Cow, io::Cursor};
-use stack_db::base::layer::Layer;
+use stack_db::base::layer::{mapper::LazyData, Layer};
 
 #[test]
 fn test_read_write() {
@@ -14,3 +14,38 @@ fn test_read_write() {
 
     assert_eq!(&*layer.read_unchecked(&(4..13)).unwrap().1, &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
 }
+
+#[test]
+fn test_lazy_sections() {
+    let mut layer = Layer::new(Cursor::new(Vec::new()));
+    layer.write_unchecked(0, Cow::Owned(vec![7; 1 << 20])).unwrap();
+    layer.write_unchecked(1 << 21, Cow::Borrowed(b"hello")).unwrap();
+    layer.flush().unwrap();
+
+    // the raw bytes stay on disk until read
+    let sections = layer.sections().unwrap().map(|x| x.unwrap()).collect::<Vec<_>>();
+    assert!(matches!(sections[..], [(ref a, LazyData::OnDisk(_)), (ref b, LazyData::OnDisk(_))] if *a == (0..1 << 20) && *b == (1 << 21..(1 << 21) + 5)));
+    let collisions = layer.check_collisions(&(1 << 20..1 << 22)).unwrap();
+    assert_eq!((collisions.len(), collisions[0].clone()), (1, 1 << 21..(1 << 21) + 5));
+    assert_eq!(layer.read_unchecked(&(1000..1004)).unwrap().1.len(), 4);
+}
+
+#[test]
+fn test_coverage() {
+    let mut layer_data = Vec::new();
+    let mut layer = Layer::new(Cursor::new(&mut layer_data));
+    layer.write_unchecked(0, Cow::Borrowed(b"start")).unwrap();
+    layer.write_unchecked(1 << 40, Cow::Borrowed(b"end")).unwrap();
+    layer.flush().unwrap();
+
+    // only the parts at the ends of the bounds are covered
+    let bounds = layer.bounds.clone().unwrap();
+    assert!(layer.coverage.overlaps(&bounds, &(0..1)));
+    assert!(!layer.coverage.overlaps(&bounds, &(1 << 36..1 << 39)));
+    assert_eq!(layer.coverage.0.iter().map(|x| x.count_ones()).sum::<u32>(), 2);
+
+    // survives reloading the layer
+    let mut layer = Layer::load(Cursor::new(&mut layer_data)).unwrap();
+    assert!(layer.check_collisions(&(1 << 36..1 << 39)).unwrap().is_empty());
+    assert_eq!(&*layer.read_unchecked(&((1 << 40)..(1 << 40) + 3)).unwrap().1, b"end");
+}